@@ -0,0 +1,6 @@
+-- Add up migration script here
+ALTER TABLE "users" ADD COLUMN email_verified_at TIMESTAMP WITH TIME ZONE;
+
+-- Accounts from before verification existed count as verified, so requiring verification
+-- doesn't lock them out
+UPDATE users SET email_verified_at = created_at;
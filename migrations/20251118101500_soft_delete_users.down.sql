@@ -0,0 +1,11 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_user_email_active;
+
+-- Without the column, deleted accounts would come back to life and could clash on email
+DELETE FROM users WHERE deleted_at IS NOT NULL;
+
+ALTER TABLE "users" ADD CONSTRAINT users_email_key UNIQUE (email);
+
+DROP INDEX IF EXISTS idx_user_deleted_at;
+
+ALTER TABLE "users" DROP COLUMN deleted_at;
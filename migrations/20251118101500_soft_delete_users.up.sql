@@ -0,0 +1,8 @@
+-- Add up migration script here
+ALTER TABLE "users" ADD COLUMN deleted_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX idx_user_deleted_at ON users(deleted_at) WHERE deleted_at IS NOT NULL;
+
+-- A deleted account must not hold on to its address during the grace period
+ALTER TABLE "users" DROP CONSTRAINT users_email_key;
+CREATE UNIQUE INDEX idx_user_email_active ON users(email) WHERE deleted_at IS NULL;
@@ -1,11 +1,11 @@
-use std::{io::IsTerminal, sync::Arc};
+use std::{io::IsTerminal, net::SocketAddr, sync::Arc};
 
 use axum::{Router, routing::get};
 use color_eyre::config::{HookBuilder, Theme};
 use tokio::net::TcpListener;
 use tower_http::trace::TraceLayer;
 
-use crate::{Result, config::Config, context::AppContext, controllers, middlewares};
+use crate::{Result, config::Config, context::AppContext, controllers, middlewares, tasks};
 
 pub struct App;
 
@@ -23,9 +23,13 @@ impl App {
 
         let ctx = Arc::new(AppContext::try_from(&config).await?);
 
+        tokio::spawn(tasks::purge_deleted_users(ctx.clone()));
+
         let router = Router::new()
             .route("/hello", get(|| async { "Hello from axum!" }))
             .nest("/auth", controllers::auth::router(&ctx))
+            .nest("/oauth", controllers::oauth::router(&ctx))
+            .merge(controllers::well_known::router(&ctx))
             .layer(
                 TraceLayer::new_for_http()
                     .make_span_with(middlewares::make_span_with)
@@ -38,6 +42,12 @@ impl App {
 
         tracing::info!("Listening on {}", config.server().url());
 
-        axum::serve(listener, router).await.map_err(Into::into)
+        // Connect info gives the session list the peer address of each login
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .map_err(Into::into)
     }
 }
@@ -1,6 +1,7 @@
-use auth::{App, Result};
+use auth::{Result, cli::Cli};
+use clap::Parser;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    App::run().await
+    Cli::parse().run().await
 }
@@ -0,0 +1,226 @@
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use jsonwebtoken::Algorithm;
+use rsa::{
+    RsaPrivateKey,
+    pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding},
+};
+
+use crate::{
+    Error, Result,
+    config::{Config, JwtConfig, KeyFamily},
+    models::token::jwk_thumbprint,
+};
+
+/// A freshly generated key, PEM encoded (or a base64 secret for HMAC)
+pub(crate) struct GeneratedKey {
+    pub(crate) private: String,
+    pub(crate) public: Option<String>,
+}
+
+/// Writes a new signing key for the access and refresh tokens to the paths configured for them
+pub fn generate(config: &Config, force: bool) -> Result<()> {
+    for (name, jwt) in [
+        ("access", config.auth().access()),
+        ("refresh", config.auth().refresh()),
+    ] {
+        generate_signing_key(name, jwt, force)?;
+    }
+
+    Ok(())
+}
+
+fn generate_signing_key(name: &str, jwt: &JwtConfig, force: bool) -> Result<()> {
+    let algorithm = jwt.algorithm();
+    let key = jwt.signing_key()?;
+    let generated = generate_key(algorithm)?;
+
+    let missing =
+        |what: &str| Error::InvalidKeyConfig(format!("key `{}` has no {} path", key.kid(), what));
+
+    if KeyFamily::from(algorithm) == KeyFamily::Hmac {
+        let secret = key.secret().ok_or_else(|| missing("secret"))?;
+        write_key(secret, &generated.private, 0o600, force)?;
+
+        println!(
+            "{name}: wrote {algorithm:?} secret `{}` to {}",
+            key.kid(),
+            secret.display()
+        );
+
+        return Ok(());
+    }
+
+    let private_key = key.private_key().ok_or_else(|| missing("private_key"))?;
+    let public_key = key.public_key().ok_or_else(|| missing("public_key"))?;
+
+    // Refuse before writing either half, so a failure can't leave a mismatched pair behind
+    if !force {
+        for path in [private_key, public_key] {
+            if path.exists() {
+                return Err(key_exists(path).into());
+            }
+        }
+    }
+
+    write_key(private_key, &generated.private, 0o600, force)?;
+    write_key(
+        public_key,
+        generated.public.as_deref().unwrap_or_default(),
+        0o644,
+        force,
+    )?;
+
+    println!(
+        "{name}: wrote {algorithm:?} key pair `{}` to {} and {}",
+        key.kid(),
+        private_key.display(),
+        public_key.display()
+    );
+
+    Ok(())
+}
+
+pub(crate) fn generate_key(algorithm: Algorithm) -> Result<GeneratedKey> {
+    let (private, public) = match (KeyFamily::from(algorithm), algorithm) {
+        (KeyFamily::Hmac, _) => {
+            let mut secret = [0u8; 64];
+            OsRng.fill_bytes(&mut secret);
+
+            return Ok(GeneratedKey {
+                private: STANDARD.encode(secret),
+                public: None,
+            });
+        }
+        (KeyFamily::Rsa, _) => {
+            let private_key = RsaPrivateKey::new(&mut OsRng, 2048)?;
+
+            (
+                private_key.to_pkcs8_pem(LineEnding::LF)?.to_string(),
+                private_key
+                    .to_public_key()
+                    .to_public_key_pem(LineEnding::LF)?,
+            )
+        }
+        (KeyFamily::Ec, Algorithm::ES384) => {
+            let private_key = p384::SecretKey::random(&mut OsRng);
+
+            (
+                private_key.to_pkcs8_pem(LineEnding::LF)?.to_string(),
+                private_key.public_key().to_public_key_pem(LineEnding::LF)?,
+            )
+        }
+        (KeyFamily::Ec, _) => {
+            let private_key = p256::SecretKey::random(&mut OsRng);
+
+            (
+                private_key.to_pkcs8_pem(LineEnding::LF)?.to_string(),
+                private_key.public_key().to_public_key_pem(LineEnding::LF)?,
+            )
+        }
+        (KeyFamily::Ed, _) => {
+            let private_key = ed25519_dalek::SigningKey::generate(&mut OsRng);
+
+            (
+                private_key.to_pkcs8_pem(LineEnding::LF)?.to_string(),
+                private_key
+                    .verifying_key()
+                    .to_public_key_pem(LineEnding::LF)?,
+            )
+        }
+    };
+
+    Ok(GeneratedKey {
+        private,
+        public: Some(public),
+    })
+}
+
+/// Creates the file with `mode` permissions; existing keys are only replaced with `force`
+fn write_key(path: &Path, contents: &str, mode: u32, force: bool) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true);
+
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        options.mode(mode);
+
+        let mut file = options
+            .open(path)
+            .map_err(|err| already_exists(path, err))?;
+        // `mode` only applies to newly created files
+        file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+        file.write_all(contents.as_bytes())?;
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = mode;
+        options
+            .open(path)
+            .map_err(|err| already_exists(path, err))?
+            .write_all(contents.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn already_exists(path: &Path, err: std::io::Error) -> Error {
+    if err.kind() == std::io::ErrorKind::AlreadyExists {
+        key_exists(path)
+    } else {
+        err.into()
+    }
+}
+
+fn key_exists(path: &Path) -> Error {
+    Error::InvalidKeyConfig(format!(
+        "{} already exists; pass --force to overwrite it",
+        path.display()
+    ))
+}
+
+/// Prints the public JWK and RFC 7638 fingerprint of every configured key
+pub fn inspect(config: &Config) -> Result<()> {
+    for (name, jwt) in [
+        ("access", config.auth().access()),
+        ("refresh", config.auth().refresh()),
+    ] {
+        let algorithm = jwt.algorithm();
+        let signing_kid = jwt.signing_key()?.kid();
+
+        for key in jwt.keys() {
+            let role = if key.kid() == signing_kid {
+                "signing"
+            } else {
+                "verify-only"
+            };
+
+            println!("{name} key `{}` ({algorithm:?}, {role})", key.kid());
+
+            match key.public_jwk(algorithm)? {
+                Some(jwk) => {
+                    println!("  fingerprint: SHA256:{}", jwk_thumbprint(&jwk));
+                    println!("  jwk: {}", serde_json::to_string(&jwk)?);
+                }
+                None => println!("  shared secret; there is no public key to publish"),
+            }
+        }
+    }
+
+    Ok(())
+}
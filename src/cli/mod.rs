@@ -0,0 +1,45 @@
+pub mod keys;
+
+use clap::{Parser, Subcommand};
+
+use crate::{App, Result, config::Config};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Authentication server")]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Start the HTTP server (the default when no command is given)
+    Serve,
+    /// Manage the token signing keys
+    #[command(subcommand)]
+    Keys(KeysCommand),
+}
+
+#[derive(Debug, Subcommand)]
+enum KeysCommand {
+    /// Generate the signing key of each token type at its configured paths
+    Generate {
+        /// Overwrite key files that already exist
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the public JWK and fingerprint of every configured key
+    Inspect,
+}
+
+impl Cli {
+    pub async fn run(self) -> Result<()> {
+        match self.command {
+            None | Some(Command::Serve) => App::run().await,
+            Some(Command::Keys(KeysCommand::Generate { force })) => {
+                keys::generate(&Config::load()?, force)
+            }
+            Some(Command::Keys(KeysCommand::Inspect)) => keys::inspect(&Config::load()?),
+        }
+    }
+}
@@ -1,28 +1,328 @@
-use std::path::PathBuf;
+use std::{
+    fmt::Display,
+    num::{NonZeroU64, NonZeroUsize},
+    path::PathBuf,
+    str::FromStr,
+};
 
-use jsonwebtoken::{DecodingKey, EncodingKey};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+        EllipticCurveKeyType, Jwk, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType,
+        PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use rsa::{
+    RsaPublicKey, pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, traits::PublicKeyParts,
+};
 use serde::Deserialize;
 
-use crate::Result;
+use crate::{Error, Result};
+
+/// The kind of key material an [`Algorithm`] signs with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyFamily {
+    Hmac,
+    Rsa,
+    Ec,
+    Ed,
+}
+
+impl From<Algorithm> for KeyFamily {
+    fn from(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => Self::Hmac,
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => Self::Rsa,
+            Algorithm::ES256 | Algorithm::ES384 => Self::Ec,
+            Algorithm::EdDSA => Self::Ed,
+        }
+    }
+}
+
+/// A single key identified by its `kid`.
+///
+/// Asymmetric algorithms read a PEM `private_key`/`public_key` pair; keys without a private key
+/// are verify-only and stay around after a rotation so tokens signed by them remain valid until
+/// they expire. HMAC algorithms read a shared `secret` instead.
+///
+/// Each part can be given inline (`*_pem`, `secret_value`), through an environment variable
+/// (`*_env`, falling back to a file named by `<VAR>_FILE`) or as a path, checked in that order.
+#[derive(Debug, Deserialize, Clone)]
+pub struct KeyConfig {
+    kid: String,
+    private_key: Option<PathBuf>,
+    private_key_pem: Option<String>,
+    private_key_env: Option<String>,
+    public_key: Option<PathBuf>,
+    public_key_pem: Option<String>,
+    public_key_env: Option<String>,
+    secret: Option<PathBuf>,
+    secret_value: Option<String>,
+    secret_env: Option<String>,
+}
+
+impl KeyConfig {
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    pub fn private_key(&self) -> Option<&PathBuf> {
+        self.private_key.as_ref()
+    }
+
+    pub fn public_key(&self) -> Option<&PathBuf> {
+        self.public_key.as_ref()
+    }
+
+    pub fn secret(&self) -> Option<&PathBuf> {
+        self.secret.as_ref()
+    }
+
+    pub fn encoding_key(&self, algorithm: Algorithm) -> Result<EncodingKey> {
+        if KeyFamily::from(algorithm) == KeyFamily::Hmac {
+            return Ok(EncodingKey::from_secret(self.read_secret()?.as_bytes()));
+        }
+
+        let contents = self.read_private_key()?;
+
+        match KeyFamily::from(algorithm) {
+            KeyFamily::Rsa => EncodingKey::from_rsa_pem(contents.as_bytes()),
+            KeyFamily::Ec => EncodingKey::from_ec_pem(contents.as_bytes()),
+            KeyFamily::Ed => EncodingKey::from_ed_pem(contents.as_bytes()),
+            KeyFamily::Hmac => unreachable!(),
+        }
+        .map_err(|err| self.load_error("private key", err).into())
+    }
+
+    pub fn decoding_key(&self, algorithm: Algorithm) -> Result<DecodingKey> {
+        if KeyFamily::from(algorithm) == KeyFamily::Hmac {
+            return Ok(DecodingKey::from_secret(self.read_secret()?.as_bytes()));
+        }
+
+        let contents = self.read_public_key()?;
+
+        match KeyFamily::from(algorithm) {
+            KeyFamily::Rsa => DecodingKey::from_rsa_pem(contents.as_bytes()),
+            KeyFamily::Ec => DecodingKey::from_ec_pem(contents.as_bytes()),
+            KeyFamily::Ed => DecodingKey::from_ed_pem(contents.as_bytes()),
+            KeyFamily::Hmac => unreachable!(),
+        }
+        .map_err(|err| self.load_error("public key", err).into())
+    }
+
+    /// The public key as a JSON Web Key carrying the configured `kid`.
+    ///
+    /// Shared secrets have no public half, so HMAC keys yield `None`.
+    pub fn public_jwk(&self, algorithm: Algorithm) -> Result<Option<Jwk>> {
+        let algorithm_parameters = match KeyFamily::from(algorithm) {
+            KeyFamily::Hmac => return Ok(None),
+            KeyFamily::Rsa => {
+                let contents = self.read_public_key()?;
+
+                let public_key = RsaPublicKey::from_public_key_pem(&contents)
+                    .or_else(|_| RsaPublicKey::from_pkcs1_pem(&contents))
+                    .map_err(|err| self.load_error("public key", err))?;
+
+                AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be()),
+                    e: URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+                })
+            }
+            KeyFamily::Ec => {
+                let contents = self.read_public_key()?;
+
+                // Uncompressed SEC1 points always carry both coordinates
+                let (curve, x, y) = if algorithm == Algorithm::ES384 {
+                    let point = p384::PublicKey::from_public_key_pem(&contents)
+                        .map_err(|err| self.load_error("public key", err))?
+                        .to_encoded_point(false);
+                    (
+                        EllipticCurve::P384,
+                        point.x().map(|x| x.to_vec()).unwrap_or_default(),
+                        point.y().map(|y| y.to_vec()).unwrap_or_default(),
+                    )
+                } else {
+                    let point = p256::PublicKey::from_public_key_pem(&contents)
+                        .map_err(|err| self.load_error("public key", err))?
+                        .to_encoded_point(false);
+                    (
+                        EllipticCurve::P256,
+                        point.x().map(|x| x.to_vec()).unwrap_or_default(),
+                        point.y().map(|y| y.to_vec()).unwrap_or_default(),
+                    )
+                };
+
+                AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                    key_type: EllipticCurveKeyType::EC,
+                    curve,
+                    x: URL_SAFE_NO_PAD.encode(x),
+                    y: URL_SAFE_NO_PAD.encode(y),
+                })
+            }
+            KeyFamily::Ed => {
+                let contents = self.read_public_key()?;
+
+                let public_key = ed25519_dalek::VerifyingKey::from_public_key_pem(&contents)
+                    .map_err(|err| self.load_error("public key", err))?;
+
+                AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: URL_SAFE_NO_PAD.encode(public_key.as_bytes()),
+                })
+            }
+        };
+
+        Ok(Some(Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(KeyAlgorithm::from_str(&format!("{:?}", algorithm))?),
+                key_id: Some(self.kid.clone()),
+                ..Default::default()
+            },
+            algorithm: algorithm_parameters,
+        }))
+    }
+
+    fn read_private_key(&self) -> Result<String> {
+        self.load(
+            "private key",
+            self.private_key_pem.as_deref(),
+            self.private_key_env.as_deref(),
+            self.private_key.as_ref(),
+        )
+    }
+
+    fn read_public_key(&self) -> Result<String> {
+        self.load(
+            "public key",
+            self.public_key_pem.as_deref(),
+            self.public_key_env.as_deref(),
+            self.public_key.as_ref(),
+        )
+    }
+
+    fn read_secret(&self) -> Result<String> {
+        let contents = self.load(
+            "secret",
+            self.secret_value.as_deref(),
+            self.secret_env.as_deref(),
+            self.secret.as_ref(),
+        )?;
+
+        Ok(contents.trim_end().to_string())
+    }
+
+    fn load(
+        &self,
+        what: &str,
+        inline: Option<&str>,
+        env: Option<&str>,
+        path: Option<&PathBuf>,
+    ) -> Result<String> {
+        if let Some(inline) = inline {
+            return Ok(unescape_newlines(inline));
+        }
+
+        if let Some(var) = env {
+            if let Ok(value) = std::env::var(var) {
+                return Ok(unescape_newlines(&value));
+            }
+
+            let file_var = format!("{}_FILE", var);
+
+            let Ok(file) = std::env::var(&file_var) else {
+                return Err(self
+                    .load_error(what, format!("neither ${} nor ${} is set", var, file_var))
+                    .into());
+            };
+
+            return std::fs::read_to_string(&file).map_err(|err| {
+                self.load_error(
+                    what,
+                    format!("reading {} (from ${}): {}", file, file_var, err),
+                )
+                .into()
+            });
+        }
+
+        if let Some(path) = path {
+            return std::fs::read_to_string(path).map_err(|err| {
+                self.load_error(what, format!("reading {}: {}", path.display(), err))
+                    .into()
+            });
+        }
+
+        Err(self.load_error(what, "no source configured").into())
+    }
+
+    fn load_error(&self, what: &str, reason: impl Display) -> Error {
+        Error::KeyLoad(format!("{} of key `{}`: {}", what, self.kid, reason))
+    }
+}
+
+/// PEMs passed through environment variables often have their newlines escaped as `\n`
+fn unescape_newlines(value: &str) -> String {
+    if value.contains('\n') {
+        value.to_string()
+    } else {
+        value.replace("\\n", "\n")
+    }
+}
 
 #[derive(Debug, Deserialize, Clone)]
-pub struct RsaJwtConfig {
-    private_key: PathBuf,
-    public_key: PathBuf,
+pub struct JwtConfig {
+    #[serde(default = "default_algorithm")]
+    algorithm: Algorithm,
+    /// `kid` of the key new tokens are signed with
+    signing_key: String,
+    keys: Vec<KeyConfig>,
+    issuer: String,
+    audience: Vec<String>,
     exp: i64,
 }
 
-impl RsaJwtConfig {
-    pub fn encoding_key(&self) -> Result<EncodingKey> {
-        let contents = std::fs::read_to_string(&self.private_key)?;
+fn default_algorithm() -> Algorithm {
+    Algorithm::RS256
+}
 
-        EncodingKey::from_rsa_pem(contents.as_bytes()).map_err(Into::into)
+impl JwtConfig {
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
     }
 
-    pub fn decoding_key(&self) -> Result<DecodingKey> {
-        let contents = std::fs::read_to_string(&self.public_key)?;
+    pub fn signing_key(&self) -> Result<&KeyConfig> {
+        self.keys
+            .iter()
+            .find(|key| key.kid == self.signing_key)
+            .ok_or_else(|| {
+                Error::InvalidKeyConfig(format!(
+                    "signing key `{}` is not configured",
+                    self.signing_key
+                ))
+                .into()
+            })
+    }
+
+    pub fn keys(&self) -> &[KeyConfig] {
+        &self.keys
+    }
 
-        DecodingKey::from_rsa_pem(contents.as_bytes()).map_err(Into::into)
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn audience(&self) -> &[String] {
+        &self.audience
     }
 
     pub fn exp(&self) -> i64 {
@@ -30,18 +330,689 @@ impl RsaJwtConfig {
     }
 }
 
+/// How access and refresh tokens are represented on the client
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TokenFormat {
+    /// Self-contained signed JWTs
+    #[serde(rename = "jwt")]
+    #[default]
+    Jwt,
+    /// Random reference strings whose details only live in Redis
+    #[serde(rename = "opaque")]
+    Opaque,
+}
+
+/// DPoP (RFC 9449) proof checking; binding is opt-in per client by sending a `DPoP` header
+#[derive(Debug, Deserialize, Clone)]
+pub struct DpopConfig {
+    /// How far, in seconds, a proof's `iat` may be from the server clock
+    #[serde(default = "default_proof_max_age")]
+    proof_max_age: i64,
+    /// Base URL proofs are issued for, overriding `server.public_url`; only needed when DPoP
+    /// requests reach the server under a different URL than the links in emails
+    public_url: Option<String>,
+}
+
+fn default_proof_max_age() -> i64 {
+    300
+}
+
+impl Default for DpopConfig {
+    fn default() -> Self {
+        Self {
+            proof_max_age: default_proof_max_age(),
+            public_url: None,
+        }
+    }
+}
+
+impl DpopConfig {
+    pub fn proof_max_age(&self) -> i64 {
+        self.proof_max_age
+    }
+
+    pub fn public_url(&self) -> Option<&str> {
+        self.public_url.as_deref()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct EmailVerificationConfig {
+    /// Refuse logins until the user has verified their email address
+    #[serde(default)]
+    required: bool,
+    /// Seconds a verification link stays valid
+    #[serde(default = "default_verification_token_exp")]
+    token_exp: i64,
+}
+
+fn default_verification_token_exp() -> i64 {
+    86400
+}
+
+impl Default for EmailVerificationConfig {
+    fn default() -> Self {
+        Self {
+            required: false,
+            token_exp: default_verification_token_exp(),
+        }
+    }
+}
+
+impl EmailVerificationConfig {
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    pub fn token_exp(&self) -> i64 {
+        self.token_exp
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PasswordResetConfig {
+    /// Seconds a password reset link stays valid
+    #[serde(default = "default_reset_token_exp")]
+    token_exp: i64,
+    /// Page of the client app that asks for the new password and posts it with the token to
+    /// `/auth/reset-password`; the emailed link is this URL with the token appended as `token`.
+    ///
+    /// Required, as the auth server has no such page of its own to fall back to.
+    link_url: String,
+}
+
+fn default_reset_token_exp() -> i64 {
+    3600
+}
+
+impl PasswordResetConfig {
+    pub fn token_exp(&self) -> i64 {
+        self.token_exp
+    }
+
+    /// The emailed reset link for `token`
+    pub fn link(&self, token: &str) -> String {
+        let separator = if self.link_url.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+
+        format!("{}{}token={}", self.link_url, separator, token)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AccountDeletionConfig {
+    /// Seconds a deleted account is kept, and could be restored by an operator, before it is
+    /// purged for good
+    #[serde(default = "default_deletion_grace_period")]
+    grace_period: i64,
+    /// Seconds between runs of the background purge; 0 is refused when the config is loaded
+    #[serde(default = "default_purge_interval")]
+    purge_interval: NonZeroU64,
+    /// Seconds after signing in during which deleting the account doesn't ask for the password
+    #[serde(default = "default_reauth_window")]
+    reauth_window: i64,
+}
+
+fn default_deletion_grace_period() -> i64 {
+    2592000
+}
+
+fn default_purge_interval() -> NonZeroU64 {
+    NonZeroU64::new(3600).unwrap()
+}
+
+fn default_reauth_window() -> i64 {
+    300
+}
+
+impl Default for AccountDeletionConfig {
+    fn default() -> Self {
+        Self {
+            grace_period: default_deletion_grace_period(),
+            purge_interval: default_purge_interval(),
+            reauth_window: default_reauth_window(),
+        }
+    }
+}
+
+impl AccountDeletionConfig {
+    pub fn grace_period(&self) -> i64 {
+        self.grace_period
+    }
+
+    pub fn purge_interval(&self) -> u64 {
+        self.purge_interval.get()
+    }
+
+    pub fn reauth_window(&self) -> i64 {
+        self.reauth_window
+    }
+}
+
+/// Rules a new password has to meet
+#[derive(Debug, Deserialize, Clone)]
+pub struct PasswordPolicy {
+    #[serde(default = "default_password_min_length")]
+    min_length: usize,
+    /// Upper bound on what gets hashed, which keeps the cost of Argon2 in check
+    #[serde(default = "default_password_max_length")]
+    max_length: usize,
+    #[serde(default)]
+    require_lowercase: bool,
+    #[serde(default)]
+    require_uppercase: bool,
+    #[serde(default)]
+    require_digit: bool,
+    /// Anything that is neither a letter nor a digit counts as a symbol
+    #[serde(default)]
+    require_symbol: bool,
+}
+
+fn default_password_min_length() -> usize {
+    8
+}
+
+fn default_password_max_length() -> usize {
+    128
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: default_password_min_length(),
+            max_length: default_password_max_length(),
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn min_length(&self) -> usize {
+        self.min_length
+    }
+
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    /// Describes every rule `password` breaks; empty if it meets the policy
+    pub fn check(&self, password: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        let length = password.chars().count();
+
+        if length < self.min_length {
+            problems.push(format!("must be at least {} characters", self.min_length));
+        }
+
+        if length > self.max_length {
+            problems.push(format!("must be at most {} characters", self.max_length));
+        }
+
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            problems.push("must contain a lowercase letter".to_string());
+        }
+
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            problems.push("must contain an uppercase letter".to_string());
+        }
+
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            problems.push("must contain a digit".to_string());
+        }
+
+        if self.require_symbol && password.chars().all(char::is_alphanumeric) {
+            problems.push("must contain a symbol".to_string());
+        }
+
+        problems
+    }
+}
+
+/// How a signed-in client proves who it is
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AuthMode {
+    /// Access and refresh tokens handed to the client
+    #[serde(rename = "token")]
+    #[default]
+    Token,
+    /// An opaque `__Host-session` cookie naming a session kept in Redis; no tokens reach the
+    /// client
+    #[serde(rename = "session")]
+    Session,
+}
+
+/// What a login does when the user already has the maximum number of sessions
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionLimitStrategy {
+    /// End the least recently created session to make room
+    #[serde(rename = "evict_oldest")]
+    #[default]
+    EvictOldest,
+    /// Refuse the new login
+    #[serde(rename = "reject")]
+    Reject,
+}
+
+/// Lifetime policies of a session (a refresh token family), on top of each token's `exp`
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SessionConfig {
+    /// Seconds a session may go without a refresh before it ends
+    idle_timeout: Option<i64>,
+    /// Seconds after login a session ends, however often it is refreshed
+    max_lifetime: Option<i64>,
+    /// Sessions a user may have at once; 0 is refused when the config is loaded
+    max_concurrent: Option<NonZeroUsize>,
+    #[serde(default)]
+    on_limit: SessionLimitStrategy,
+}
+
+impl SessionConfig {
+    pub fn idle_timeout(&self) -> Option<i64> {
+        self.idle_timeout
+    }
+
+    pub fn max_lifetime(&self) -> Option<i64> {
+        self.max_lifetime
+    }
+
+    pub fn max_concurrent(&self) -> Option<usize> {
+        self.max_concurrent.map(NonZeroUsize::get)
+    }
+
+    pub fn on_limit(&self) -> SessionLimitStrategy {
+        self.on_limit
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct AuthConfig {
-    access: RsaJwtConfig,
-    refresh: RsaJwtConfig,
+    #[serde(default)]
+    mode: AuthMode,
+    #[serde(default)]
+    token_format: TokenFormat,
+    access: JwtConfig,
+    refresh: JwtConfig,
+    #[serde(default)]
+    dpop: DpopConfig,
+    #[serde(default)]
+    session: SessionConfig,
+    #[serde(default)]
+    password: PasswordPolicy,
+    #[serde(default)]
+    email_verification: EmailVerificationConfig,
+    password_reset: PasswordResetConfig,
+    #[serde(default)]
+    account_deletion: AccountDeletionConfig,
 }
 
 impl AuthConfig {
-    pub fn access(&self) -> &RsaJwtConfig {
+    pub fn mode(&self) -> AuthMode {
+        self.mode
+    }
+
+    pub fn token_format(&self) -> TokenFormat {
+        self.token_format
+    }
+
+    pub fn access(&self) -> &JwtConfig {
         &self.access
     }
 
-    pub fn refresh(&self) -> &RsaJwtConfig {
+    pub fn refresh(&self) -> &JwtConfig {
         &self.refresh
     }
+
+    pub fn dpop(&self) -> &DpopConfig {
+        &self.dpop
+    }
+
+    pub fn session(&self) -> &SessionConfig {
+        &self.session
+    }
+
+    pub fn password(&self) -> &PasswordPolicy {
+        &self.password
+    }
+
+    pub fn email_verification(&self) -> &EmailVerificationConfig {
+        &self.email_verification
+    }
+
+    pub fn password_reset(&self) -> &PasswordResetConfig {
+        &self.password_reset
+    }
+
+    pub fn account_deletion(&self) -> &AccountDeletionConfig {
+        &self.account_deletion
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{Header, Validation};
+    use serde_json::json;
+
+    use super::*;
+    use crate::cli::keys::generate_key;
+
+    /// A key with freshly generated material, configured inline
+    fn inline_key(algorithm: Algorithm) -> KeyConfig {
+        let generated = generate_key(algorithm).unwrap();
+
+        let config = match generated.public {
+            Some(public) => json!({
+                "kid": "k1",
+                "private_key_pem": generated.private,
+                "public_key_pem": public,
+            }),
+            None => json!({"kid": "k1", "secret_value": generated.private}),
+        };
+
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn public_jwk_verifies_what_the_private_key_signs() {
+        for algorithm in [
+            Algorithm::RS256,
+            Algorithm::PS256,
+            Algorithm::ES256,
+            Algorithm::ES384,
+            Algorithm::EdDSA,
+        ] {
+            let key = inline_key(algorithm);
+            let jwk = key.public_jwk(algorithm).unwrap().unwrap();
+
+            assert_eq!(jwk.common.key_id.as_deref(), Some("k1"));
+            assert_eq!(jwk.common.public_key_use, Some(PublicKeyUse::Signature));
+            assert_eq!(
+                jwk.common.key_algorithm.map(|alg| format!("{alg:?}")),
+                Some(format!("{algorithm:?}"))
+            );
+
+            let token = jsonwebtoken::encode(
+                &Header::new(algorithm),
+                &json!({"sub": "someone"}),
+                &key.encoding_key(algorithm).unwrap(),
+            )
+            .unwrap();
+
+            let mut validation = Validation::new(algorithm);
+            validation.validate_exp = false;
+            validation.set_required_spec_claims::<&str>(&[]);
+
+            let decoded = jsonwebtoken::decode::<serde_json::Value>(
+                &token,
+                &DecodingKey::from_jwk(&jwk).unwrap(),
+                &validation,
+            );
+            assert!(decoded.is_ok(), "{algorithm:?}: {decoded:?}");
+        }
+    }
+
+    #[test]
+    fn public_jwk_has_the_members_of_its_key_type() {
+        let rsa = inline_key(Algorithm::RS256).public_jwk(Algorithm::RS256);
+        assert!(matches!(
+            rsa.unwrap().unwrap().algorithm,
+            AlgorithmParameters::RSA(RSAKeyParameters { ref e, .. }) if e == "AQAB"
+        ));
+
+        let ec = inline_key(Algorithm::ES384).public_jwk(Algorithm::ES384);
+        assert!(matches!(
+            ec.unwrap().unwrap().algorithm,
+            AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                curve: EllipticCurve::P384,
+                ref x,
+                ref y,
+                ..
+            }) if URL_SAFE_NO_PAD.decode(x).unwrap().len() == 48
+                && URL_SAFE_NO_PAD.decode(y).unwrap().len() == 48
+        ));
+
+        let ed = inline_key(Algorithm::EdDSA).public_jwk(Algorithm::EdDSA);
+        assert!(matches!(
+            ed.unwrap().unwrap().algorithm,
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                curve: EllipticCurve::Ed25519,
+                ref x,
+                ..
+            }) if URL_SAFE_NO_PAD.decode(x).unwrap().len() == 32
+        ));
+    }
+
+    fn secret_key(config: serde_json::Value) -> KeyConfig {
+        let mut key = json!({"kid": "k1"});
+        key.as_object_mut()
+            .unwrap()
+            .extend(config.as_object().unwrap().clone());
+
+        serde_json::from_value(key).unwrap()
+    }
+
+    fn temp_file(contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("auth-key-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn set_env(var: &str, value: impl AsRef<std::ffi::OsStr>) {
+        // SAFETY: every test uses variables of its own, and nothing else reads them
+        unsafe { std::env::set_var(var, value) };
+    }
+
+    #[test]
+    fn loads_inline_then_env_then_path() {
+        let path = temp_file("from-path");
+        set_env("AUTH_TEST_ORDER_SECRET", "from-env");
+
+        let inline = secret_key(json!({
+            "secret_value": "inline",
+            "secret_env": "AUTH_TEST_ORDER_SECRET",
+            "secret": path,
+        }));
+        assert_eq!(inline.read_secret().unwrap(), "inline");
+
+        let env = secret_key(json!({"secret_env": "AUTH_TEST_ORDER_SECRET", "secret": path}));
+        assert_eq!(env.read_secret().unwrap(), "from-env");
+
+        let file = secret_key(json!({"secret": path}));
+        assert_eq!(file.read_secret().unwrap(), "from-path");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn falls_back_to_a_file_named_by_the_file_variable() {
+        let path = temp_file("from-secret\\nfile\n");
+        set_env("AUTH_TEST_FALLBACK_SECRET_FILE", &path);
+
+        let key = secret_key(json!({"secret_env": "AUTH_TEST_FALLBACK_SECRET"}));
+        // Files are not unescaped, but a secret loses its trailing newline
+        assert_eq!(key.read_secret().unwrap(), "from-secret\\nfile");
+
+        set_env("AUTH_TEST_FALLBACK_SECRET", "from-env");
+        assert_eq!(key.read_secret().unwrap(), "from-env");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reports_a_missing_source() {
+        let unset = secret_key(json!({"secret_env": "AUTH_TEST_UNSET_SECRET"}));
+        let err = unset.read_secret().unwrap_err().to_string();
+        assert!(err.contains("$AUTH_TEST_UNSET_SECRET"), "{err}");
+        assert!(err.contains("$AUTH_TEST_UNSET_SECRET_FILE"), "{err}");
+
+        let missing_file = secret_key(json!({"secret": "/nonexistent/auth-secret"}));
+        let err = missing_file.read_secret().unwrap_err().to_string();
+        assert!(err.contains("/nonexistent/auth-secret"), "{err}");
+
+        let none = secret_key(json!({}));
+        let err = none.read_secret().unwrap_err().to_string();
+        assert!(err.contains("no source configured"), "{err}");
+    }
+
+    #[test]
+    fn unescapes_newlines_of_single_line_values() {
+        assert_eq!(
+            unescape_newlines("-----BEGIN-----\\nabc\\n-----END-----"),
+            "-----BEGIN-----\nabc\n-----END-----"
+        );
+        assert_eq!(unescape_newlines("no newlines"), "no newlines");
+
+        // A value with real newlines is already a PEM and is left alone
+        assert_eq!(unescape_newlines("line\nwith \\n"), "line\nwith \\n");
+    }
+
+    #[test]
+    fn loads_escaped_pems_from_the_environment() {
+        let generated = generate_key(Algorithm::ES256).unwrap();
+        set_env(
+            "AUTH_TEST_ESCAPED_PRIVATE_KEY",
+            generated.private.replace('\n', "\\n"),
+        );
+        set_env(
+            "AUTH_TEST_ESCAPED_PUBLIC_KEY",
+            generated.public.unwrap().replace('\n', "\\n"),
+        );
+
+        let key = secret_key(json!({
+            "private_key_env": "AUTH_TEST_ESCAPED_PRIVATE_KEY",
+            "public_key_env": "AUTH_TEST_ESCAPED_PUBLIC_KEY",
+        }));
+
+        assert!(key.encoding_key(Algorithm::ES256).is_ok());
+        assert!(key.decoding_key(Algorithm::ES256).is_ok());
+        assert!(key.public_jwk(Algorithm::ES256).unwrap().is_some());
+    }
+
+    #[test]
+    fn shared_secrets_have_no_public_jwk() {
+        let key = inline_key(Algorithm::HS256);
+
+        assert!(key.public_jwk(Algorithm::HS256).unwrap().is_none());
+    }
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 16,
+            ..PasswordPolicy::default()
+        }
+    }
+
+    #[test]
+    fn default_policy_only_checks_length() {
+        let policy = PasswordPolicy::default();
+
+        assert!(policy.check("password").is_empty());
+        assert_eq!(policy.check("short"), ["must be at least 8 characters"]);
+    }
+
+    #[test]
+    fn bounds_the_length() {
+        let policy = policy();
+
+        assert!(policy.check(&"a".repeat(8)).is_empty());
+        assert!(policy.check(&"a".repeat(16)).is_empty());
+        assert_eq!(
+            policy.check(&"a".repeat(7)),
+            ["must be at least 8 characters"]
+        );
+        assert_eq!(
+            policy.check(&"a".repeat(17)),
+            ["must be at most 16 characters"]
+        );
+    }
+
+    #[test]
+    fn counts_characters_rather_than_bytes() {
+        let policy = policy();
+
+        assert!(policy.check(&"é".repeat(16)).is_empty());
+        assert_eq!(
+            policy.check(&"é".repeat(4)),
+            ["must be at least 8 characters"]
+        );
+    }
+
+    #[test]
+    fn requires_a_lowercase_letter() {
+        let policy = PasswordPolicy {
+            require_lowercase: true,
+            ..policy()
+        };
+
+        assert!(policy.check("PASSWORDs").is_empty());
+        assert_eq!(
+            policy.check("PASSWORD1"),
+            ["must contain a lowercase letter"]
+        );
+    }
+
+    #[test]
+    fn requires_an_uppercase_letter() {
+        let policy = PasswordPolicy {
+            require_uppercase: true,
+            ..policy()
+        };
+
+        assert!(policy.check("Password").is_empty());
+        assert_eq!(
+            policy.check("password1"),
+            ["must contain an uppercase letter"]
+        );
+    }
+
+    #[test]
+    fn requires_a_digit() {
+        let policy = PasswordPolicy {
+            require_digit: true,
+            ..policy()
+        };
+
+        assert!(policy.check("password1").is_empty());
+        assert_eq!(policy.check("password"), ["must contain a digit"]);
+    }
+
+    #[test]
+    fn requires_a_symbol() {
+        let policy = PasswordPolicy {
+            require_symbol: true,
+            ..policy()
+        };
+
+        assert!(policy.check("password!").is_empty());
+        assert!(policy.check("pass word").is_empty());
+        assert_eq!(policy.check("password1"), ["must contain a symbol"]);
+    }
+
+    #[test]
+    fn reports_every_broken_rule() {
+        let policy = PasswordPolicy {
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+            ..policy()
+        };
+
+        assert!(policy.check("Passw0rd!").is_empty());
+        assert_eq!(
+            policy.check("abc"),
+            [
+                "must be at least 8 characters",
+                "must contain an uppercase letter",
+                "must contain a digit",
+                "must contain a symbol",
+            ]
+        );
+    }
 }
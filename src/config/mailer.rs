@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+/// How the SMTP connection is secured
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SmtpTls {
+    /// Upgrade a plain connection with `STARTTLS`, usually on port 587
+    #[serde(rename = "starttls")]
+    #[default]
+    StartTls,
+    /// TLS from the first byte, usually on port 465
+    #[serde(rename = "tls")]
+    Tls,
+    /// No encryption; only for a relay on a trusted network or a local test server
+    #[serde(rename = "none")]
+    None,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "transport")]
+pub enum MailTransport {
+    #[serde(rename = "smtp")]
+    Smtp {
+        host: String,
+        port: Option<u16>,
+        username: Option<String>,
+        password: Option<String>,
+        #[serde(default)]
+        tls: SmtpTls,
+    },
+    /// Logs every email and, when `dir` is set, writes it there as an `.eml` file. Meant for
+    /// local development, as the logs include the links sent to users.
+    #[serde(rename = "file")]
+    File { dir: Option<PathBuf> },
+}
+
+impl Default for MailTransport {
+    fn default() -> Self {
+        Self::File { dir: None }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MailerConfig {
+    /// Sender of every email, e.g. `Auth <no-reply@example.com>`
+    from: String,
+    #[serde(flatten)]
+    transport: MailTransport,
+}
+
+impl Default for MailerConfig {
+    fn default() -> Self {
+        Self {
+            from: "Auth <no-reply@localhost>".to_string(),
+            transport: MailTransport::default(),
+        }
+    }
+}
+
+impl MailerConfig {
+    pub fn from(&self) -> &str {
+        &self.from
+    }
+
+    pub fn transport(&self) -> &MailTransport {
+        &self.transport
+    }
+}
@@ -1,15 +1,23 @@
 pub mod auth;
 pub mod db;
 pub mod log;
+pub mod mailer;
+pub mod oauth;
 
 use serde::Deserialize;
 
 use crate::Result;
 
 pub use self::{
-    auth::{AuthConfig, RsaJwtConfig},
+    auth::{
+        AccountDeletionConfig, AuthConfig, AuthMode, DpopConfig, EmailVerificationConfig,
+        JwtConfig, KeyConfig, KeyFamily, PasswordPolicy, PasswordResetConfig, SessionConfig,
+        SessionLimitStrategy, TokenFormat,
+    },
     db::{DatabaseConfig, RedisConfig},
     log::Logger,
+    mailer::{MailTransport, MailerConfig, SmtpTls},
+    oauth::{OAuthClient, OAuthConfig},
 };
 
 #[derive(Debug, Deserialize, Clone)]
@@ -17,6 +25,9 @@ pub struct ServerConfig {
     protocol: String,
     host: String,
     port: u16,
+    /// Externally visible base URL (e.g. behind a reverse proxy), used in links sent to users and
+    /// as the base of DPoP `htu`s; defaults to `url()`
+    public_url: Option<String>,
 }
 
 impl ServerConfig {
@@ -27,6 +38,13 @@ impl ServerConfig {
     pub fn url(&self) -> String {
         format!("{}://{}", &self.protocol, self.address())
     }
+
+    pub fn public_url(&self) -> String {
+        self.public_url
+            .as_deref()
+            .map(|url| url.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| self.url())
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -36,6 +54,10 @@ pub struct Config {
     database: DatabaseConfig,
     redis: RedisConfig,
     auth: AuthConfig,
+    #[serde(default)]
+    oauth: OAuthConfig,
+    #[serde(default)]
+    mailer: MailerConfig,
 }
 
 impl Config {
@@ -85,6 +107,14 @@ impl Config {
     pub fn auth(&self) -> &AuthConfig {
         &self.auth
     }
+
+    pub fn oauth(&self) -> &OAuthConfig {
+        &self.oauth
+    }
+
+    pub fn mailer(&self) -> &MailerConfig {
+        &self.mailer
+    }
 }
 
 #[derive(Debug, Deserialize, Clone, Default)]
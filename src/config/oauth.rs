@@ -0,0 +1,47 @@
+use serde::Deserialize;
+
+/// A confidential client allowed to call the OAuth endpoints
+#[derive(Debug, Deserialize, Clone)]
+pub struct OAuthClient {
+    client_id: String,
+    client_secret: String,
+}
+
+impl OAuthClient {
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// Compares in constant time so the secret can't be recovered byte by byte
+    pub fn verify_secret(&self, secret: &str) -> bool {
+        let expected = self.client_secret.as_bytes();
+        let given = secret.as_bytes();
+
+        expected.len() == given.len()
+            && expected
+                .iter()
+                .zip(given)
+                .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct OAuthConfig {
+    #[serde(default)]
+    clients: Vec<OAuthClient>,
+}
+
+impl OAuthConfig {
+    pub fn clients(&self) -> &[OAuthClient] {
+        &self.clients
+    }
+
+    /// Looks up a client and checks its secret
+    pub fn authenticate(&self, client_id: &str, client_secret: &str) -> Option<&OAuthClient> {
+        self.clients
+            .iter()
+            .find(|client| client.client_id == client_id)
+            .filter(|client| client.verify_secret(client_secret))
+    }
+}
@@ -1,12 +1,26 @@
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
-use redis::{AsyncTypedCommands, aio::MultiplexedConnection};
+use std::{collections::HashMap, sync::Arc};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, jwk::JwkSet};
+use redis::{
+    AsyncTypedCommands, ExistenceCheck, SetExpiry, SetOptions, aio::MultiplexedConnection,
+};
+use serde::{Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
 use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::{
-    config::{Config, RsaJwtConfig},
+    config::{Config, JwtConfig, SessionLimitStrategy, TokenFormat},
     error::Report,
-    models::token::{TokenClaims, TokenDetails},
+    mailer::{self, Mailer},
+    middlewares::AuthError,
+    models::{
+        dpop::{DpopClaims, DpopProof},
+        session::{ClientInfo, Session},
+        token::{AccessClaims, NoClaims, OpaqueTokenRecord, TokenClaims, TokenDetails},
+    },
 };
 
 #[derive(Clone)]
@@ -15,6 +29,21 @@ pub struct AppContext {
     pub auth: AuthContext,
     pub db: PgPool,
     pub redis: MultiplexedConnection,
+    pub mailer: Arc<dyn Mailer>,
+}
+
+/// Redis keys for secrets handed to clients hold a hash of the secret, so a leaked key doesn't
+/// leak a usable token
+fn token_hash(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// 32 random bytes, URL-safe so they can go straight into a link or cookie
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    URL_SAFE_NO_PAD.encode(bytes)
 }
 
 impl AppContext {
@@ -30,31 +59,618 @@ impl AppContext {
             conn.set(&key, &value).await?;
         }
 
+        // Track the live token of the family so the whole family can be revoked at once
+        if let Some(family_id) = token_details.family_id {
+            let family_key = format!("refresh_token_family:{}", family_id);
+            let token_id = token_details.token_id.to_string();
+
+            if let Some(expires_in) = token_details.expires_in {
+                let ttl = (expires_in - chrono::Utc::now().timestamp()) as u64;
+                conn.set_ex(&family_key, &token_id, ttl).await?;
+            } else {
+                conn.set(&family_key, &token_id).await?;
+            }
+        }
+
         Ok(())
     }
 
+    pub async fn find_refresh_token(&self, token_id: Uuid) -> Result<Option<TokenDetails>, Report> {
+        let mut conn = self.redis.clone();
+
+        conn.get(format!("refresh_token:{}", token_id))
+            .await?
+            .map(|details| serde_json::from_str(&details))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Revokes a refresh token along with the session it belongs to, so access tokens issued
+    /// from the same grant stop working too (RFC 7009 §2.1)
     pub async fn revoke_refresh_token(&self, token_id: Uuid) -> Result<(), Report> {
+        if let Some(family_id) = self
+            .find_refresh_token(token_id)
+            .await?
+            .and_then(|stored| stored.family_id)
+        {
+            return self.revoke_session(family_id).await;
+        }
+
+        let mut conn = self.redis.clone();
+        conn.del(format!("refresh_token:{}", token_id)).await?;
+
+        Ok(())
+    }
+
+    /// Revokes the live refresh token of a family and drops its session record; access tokens
+    /// already issued keep working, so callers go through [`Self::revoke_session`]
+    async fn revoke_refresh_family(&self, family_id: Uuid) -> Result<(), Report> {
+        let mut conn = self.redis.clone();
+        let family_key = format!("refresh_token_family:{}", family_id);
+
+        if let Some(token_id) = conn.get(&family_key).await? {
+            conn.del(format!("refresh_token:{}", token_id)).await?;
+        }
+
+        conn.del(&family_key).await?;
+
+        // The family is the session, so it disappears from the user's session list too
+        if let Some(session) = self.find_session(family_id).await? {
+            conn.del(format!("session:{}", family_id)).await?;
+            conn.srem(
+                format!("user_sessions:{}", session.user_pid),
+                family_id.to_string(),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Ends a session: its refresh token stops rotating and access tokens issued for it are
+    /// rejected from now on
+    pub async fn revoke_session(&self, id: Uuid) -> Result<(), Report> {
+        self.revoke_refresh_family(id).await?;
+
+        // Access tokens issued for the session are all gone once the longest one expires
+        let mut conn = self.redis.clone();
+        conn.set_ex(
+            format!("revoked_session:{}", id),
+            1,
+            self.auth.access.exp() as u64,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Ends every session of a user, except `keep` when given
+    pub async fn revoke_user_sessions(
+        &self,
+        user_pid: Uuid,
+        keep: Option<Uuid>,
+    ) -> Result<(), Report> {
+        for session in self.list_sessions(user_pid).await? {
+            if Some(session.id) != keep {
+                self.revoke_session(session.id).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn is_session_revoked(&self, id: Uuid) -> Result<bool, Report> {
+        let mut conn = self.redis.clone();
+        let key = format!("revoked_session:{}", id);
+
+        conn.exists(&key).await.map_err(Into::into)
+    }
+
+    /// Mints an access token for the session of `refresh_token`, bound to the same DPoP key
+    pub async fn issue_access_token(
+        &self,
+        refresh_token: &TokenDetails,
+    ) -> Result<TokenDetails, Report> {
+        let claims = AccessClaims {
+            sid: refresh_token.family_id,
+            dpop: DpopClaims::bound_to(refresh_token.jkt.as_deref()),
+        };
+
+        let mut access_token = self
+            .auth
+            .access
+            .generate_token_with_claims(refresh_token.user_pid, claims)
+            .await?;
+        access_token.family_id = refresh_token.family_id;
+
+        Ok(access_token)
+    }
+
+    /// Makes sure a user has room for one more session under `max_concurrent`, by ending their
+    /// oldest sessions or refusing, as configured
+    pub async fn reserve_session_slot(&self, user_pid: Uuid) -> Result<(), Report> {
+        let policy = self.config.auth().session();
+
+        let Some(max) = policy.max_concurrent() else {
+            return Ok(());
+        };
+
+        let mut sessions = self.list_sessions(user_pid).await?;
+
+        if sessions.len() < max {
+            return Ok(());
+        }
+
+        if policy.on_limit() == SessionLimitStrategy::Reject {
+            return Err(crate::Error::Auth(AuthError::SessionLimitReached).into());
+        }
+
+        sessions.sort_by_key(|session| session.created_at);
+
+        // Leave `max - 1` sessions so the new one fits
+        let excess = sessions.len() + 1 - max;
+
+        for session in sessions.iter().take(excess) {
+            tracing::info!(
+                session_id = %session.id,
+                user_pid = %user_pid,
+                "Session evicted by concurrent session limit"
+            );
+
+            self.revoke_session(session.id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Records the session started by a login; `refresh_token` is the first token of its family
+    pub async fn start_session(
+        &self,
+        refresh_token: &TokenDetails,
+        client: &ClientInfo,
+    ) -> Result<Session, Report> {
+        let family_id = refresh_token
+            .family_id
+            .ok_or(crate::Error::Auth(AuthError::TokenCreation))?;
+
+        let session = Session::new(family_id, refresh_token.user_pid, client);
+        self.save_session(&session, refresh_token).await?;
+
+        Ok(session)
+    }
+
+    /// Marks the session of a freshly rotated refresh token as used and extends it to the new
+    /// token's lifetime
+    async fn touch_session(
+        &self,
+        refresh_token: &TokenDetails,
+        client: &ClientInfo,
+    ) -> Result<(), Report> {
+        let Some(family_id) = refresh_token.family_id else {
+            return Ok(());
+        };
+
+        // Families issued before sessions were tracked get a record on their next rotation
+        let session = match self.find_session(family_id).await? {
+            Some(mut session) => {
+                session.touch(client);
+                session
+            }
+            None => Session::new(family_id, refresh_token.user_pid, client),
+        };
+
+        self.save_session(&session, refresh_token).await
+    }
+
+    /// Stores a session for as long as its live refresh token and adds it to the user's index
+    async fn save_session(
+        &self,
+        session: &Session,
+        refresh_token: &TokenDetails,
+    ) -> Result<(), Report> {
+        let mut conn = self.redis.clone();
+        let key = format!("session:{}", session.id);
+        let index_key = format!("user_sessions:{}", session.user_pid);
+
+        let ttl = refresh_token
+            .expires_in
+            .map(|expires_in| expires_in - chrono::Utc::now().timestamp())
+            .filter(|ttl| *ttl > 0)
+            .unwrap_or(self.auth.refresh.exp());
+
+        conn.set_ex(&key, serde_json::to_string(session)?, ttl as u64)
+            .await?;
+        conn.sadd(&index_key, session.id.to_string()).await?;
+        // No session outlives a refresh token, so neither should an index left untouched that long
+        conn.expire(&index_key, self.auth.refresh.exp()).await?;
+
+        Ok(())
+    }
+
+    fn session_cookie_key(token: &str) -> String {
+        format!("session_cookie:{}", token_hash(token))
+    }
+
+    /// Claims `key` for `seconds`; `false` if it is already claimed. Used to rate limit actions
+    /// such as sending emails.
+    pub async fn try_cooldown(&self, key: &str, seconds: u64) -> Result<bool, Report> {
+        let mut conn = self.redis.clone();
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::EX(seconds));
+
+        Ok(conn.set_options(key, 1, options).await?.is_some())
+    }
+
+    /// Issues a random token for `purpose` (e.g. emailed links) that resolves to `value` once,
+    /// until `ttl` seconds have passed
+    pub async fn issue_one_time_token<T: Serialize>(
+        &self,
+        purpose: &str,
+        value: &T,
+        ttl: i64,
+    ) -> Result<String, Report> {
+        let token = random_token();
+        let key = format!("{}_token:{}", purpose, token_hash(&token));
+
+        let mut conn = self.redis.clone();
+        conn.set_ex(&key, serde_json::to_string(value)?, ttl as u64)
+            .await?;
+
+        Ok(token)
+    }
+
+    /// Redeems a token issued for `purpose`; `None` if it is unknown, expired or already used
+    pub async fn consume_one_time_token<T: DeserializeOwned>(
+        &self,
+        purpose: &str,
+        token: &str,
+    ) -> Result<Option<T>, Report> {
+        let key = format!("{}_token:{}", purpose, token_hash(token));
+
+        let mut conn = self.redis.clone();
+
+        conn.get_del(&key)
+            .await?
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Starts a server-side session and returns the opaque value of its cookie.
+    ///
+    /// Only a hash of the cookie is kept, pointing at the session record; the session lasts the
+    /// refresh token lifetime from its last use.
+    pub async fn start_cookie_session(
+        &self,
+        user_pid: Uuid,
+        client: &ClientInfo,
+    ) -> Result<String, Report> {
+        let token = random_token();
+
+        let session = Session::new(Uuid::new_v4(), user_pid, client);
+        let details = self.cookie_session_details(&session);
+
+        self.save_session(&session, &details).await?;
+
+        let mut conn = self.redis.clone();
+        conn.set_ex(
+            Self::session_cookie_key(&token),
+            session.id.to_string(),
+            self.auth.refresh.exp() as u64,
+        )
+        .await?;
+
+        Ok(token)
+    }
+
+    /// Resolves a session cookie to the identity `AuthLayer` hands to handlers, the same shape
+    /// the token path produces
+    pub async fn resolve_session_cookie(
+        &self,
+        token: &str,
+        client: &ClientInfo,
+    ) -> Result<TokenDetails, Report> {
+        let mut conn = self.redis.clone();
+        let key = Self::session_cookie_key(token);
+
+        let Some(id) = conn.get(&key).await? else {
+            return Err(crate::Error::Auth(AuthError::InvalidToken).into());
+        };
+
+        let id = Uuid::parse_str(&id)?;
+
+        // A revoked session loses its record while the cookie may still be around
+        let Some(mut session) = self.find_session(id).await? else {
+            conn.del(&key).await?;
+            return Err(crate::Error::Auth(AuthError::TokenRevoked).into());
+        };
+
+        self.enforce_session_policy(id).await?;
+
+        // Sliding expiry; writes are skipped for requests in quick succession
+        if (chrono::Utc::now() - session.last_used_at).num_seconds() >= 60 {
+            session.touch(client);
+
+            let details = self.cookie_session_details(&session);
+            self.save_session(&session, &details).await?;
+            conn.expire(&key, self.auth.refresh.exp()).await?;
+        }
+
+        Ok(self.cookie_session_details(&session))
+    }
+
+    /// A cookie session expressed as token details: the session id stands in for both the token
+    /// id and the family, and it expires the refresh lifetime after its last use
+    fn cookie_session_details(&self, session: &Session) -> TokenDetails {
+        TokenDetails {
+            token: None,
+            token_id: session.id,
+            user_pid: session.user_pid,
+            expires_in: Some(session.last_used_at.timestamp() + self.auth.refresh.exp()),
+            issued_at: Some(session.created_at.timestamp()),
+            family_id: Some(session.id),
+            jkt: None,
+        }
+    }
+
+    pub async fn find_session(&self, id: Uuid) -> Result<Option<Session>, Report> {
+        let mut conn = self.redis.clone();
+
+        conn.get(format!("session:{}", id))
+            .await?
+            .map(|session| serde_json::from_str(&session))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Lists the live sessions of a user, most recently used first.
+    ///
+    /// Sessions expire on their own, so ids whose record is gone are pruned from the index here.
+    pub async fn list_sessions(&self, user_pid: Uuid) -> Result<Vec<Session>, Report> {
+        let mut conn = self.redis.clone();
+        let index_key = format!("user_sessions:{}", user_pid);
+
+        let mut sessions = Vec::new();
+
+        for id in conn.smembers(&index_key).await? {
+            match self.find_session(Uuid::parse_str(&id)?).await? {
+                Some(session) => sessions.push(session),
+                None => {
+                    conn.srem(&index_key, &id).await?;
+                }
+            }
+        }
+
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_used_at));
+
+        Ok(sessions)
+    }
+
+    /// Exchanges a presented refresh token for a new one in the same family.
+    ///
+    /// The presented token is retired and remembered for the rest of its lifetime; presenting
+    /// it again is treated as theft and revokes the whole session, access tokens included.
+    ///
+    /// A refresh token bound to a DPoP key can only be rotated with a proof from that key (`jkt`);
+    /// the rotated token stays bound, or becomes bound if a proof is presented for the first time.
+    pub async fn rotate_refresh_token(
+        &self,
+        presented: &TokenDetails,
+        jkt: Option<&str>,
+        client: &ClientInfo,
+    ) -> Result<TokenDetails, Report> {
+        let mut conn = self.redis.clone();
+        let key = format!("refresh_token:{}", presented.token_id);
+        let rotated_key = format!("rotated_refresh_token:{}", presented.token_id);
+
+        // GETDEL claims the token atomically so two concurrent refreshes cannot both succeed
+        let Some(stored) = conn.get_del(&key).await? else {
+            if let Some(family_id) = conn.get(&rotated_key).await? {
+                let family_id = Uuid::parse_str(&family_id)?;
+
+                tracing::warn!(
+                    token_id = %presented.token_id,
+                    family_id = %family_id,
+                    user_pid = %presented.user_pid,
+                    "Refresh token reuse detected; revoking token family"
+                );
+
+                self.revoke_session(family_id).await?;
+
+                return Err(crate::Error::Auth(AuthError::RefreshTokenReused).into());
+            }
+
+            return Err(crate::Error::Auth(AuthError::InvalidToken).into());
+        };
+
+        let stored = serde_json::from_str::<TokenDetails>(&stored)?;
+        let family_id = stored.family_id.unwrap_or_else(Uuid::new_v4);
+
+        // A bound token presented without its key was lifted from the client it was issued to
+        if stored.jkt.is_some() && stored.jkt.as_deref() != jkt {
+            tracing::warn!(
+                token_id = %presented.token_id,
+                family_id = %family_id,
+                user_pid = %presented.user_pid,
+                "DPoP-bound refresh token presented without its key; revoking token family"
+            );
+
+            self.revoke_session(family_id).await?;
+
+            return Err(crate::Error::Auth(AuthError::InvalidDpopProof).into());
+        }
+
+        self.enforce_session_policy(family_id).await?;
+
+        let jkt = stored.jkt.as_deref().or(jkt);
+
+        let ttl = stored
+            .expires_in
+            .map(|expires_in| expires_in - chrono::Utc::now().timestamp())
+            .filter(|ttl| *ttl > 0)
+            .unwrap_or(self.auth.refresh.exp()) as u64;
+        conn.set_ex(&rotated_key, family_id.to_string(), ttl)
+            .await?;
+
+        let mut rotated = self
+            .auth
+            .refresh
+            .generate_token_with_claims(stored.user_pid, DpopClaims::bound_to(jkt))
+            .await?;
+        rotated.family_id = Some(family_id);
+        rotated.jkt = jkt.map(ToString::to_string);
+
+        self.store_refresh_token(&rotated).await?;
+        self.touch_session(&rotated, client).await?;
+
+        Ok(rotated)
+    }
+
+    /// Ends the session and fails if it has been idle for longer than the idle timeout or has
+    /// outlived the maximum session lifetime
+    async fn enforce_session_policy(&self, family_id: Uuid) -> Result<(), Report> {
+        let policy = self.config.auth().session();
+
+        let Some(session) = self.find_session(family_id).await? else {
+            return Ok(());
+        };
+
+        let now = chrono::Utc::now();
+        let idle = (now - session.last_used_at).num_seconds();
+        let age = (now - session.created_at).num_seconds();
+
+        let idle_expired = policy.idle_timeout().is_some_and(|timeout| idle > timeout);
+        let lifetime_expired = policy.max_lifetime().is_some_and(|max| age > max);
+
+        if idle_expired || lifetime_expired {
+            tracing::info!(
+                session_id = %family_id,
+                user_pid = %session.user_pid,
+                idle,
+                age,
+                "Session expired by policy"
+            );
+
+            self.revoke_session(family_id).await?;
+
+            return Err(crate::Error::Auth(AuthError::SessionExpired).into());
+        }
+
+        Ok(())
+    }
+
+    /// The stored record of a family's live refresh token, if the family is still active
+    pub async fn find_family_refresh_token(
+        &self,
+        family_id: Uuid,
+    ) -> Result<Option<TokenDetails>, Report> {
+        let mut conn = self.redis.clone();
+
+        let Some(token_id) = conn
+            .get(format!("refresh_token_family:{}", family_id))
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        self.find_refresh_token(Uuid::parse_str(&token_id)?).await
+    }
+
+    pub async fn is_refresh_token_active(&self, token_id: Uuid) -> Result<bool, Report> {
         let mut conn = self.redis.clone();
         let key = format!("refresh_token:{}", token_id);
 
-        conn.del(&key).await?;
+        conn.exists(&key).await.map_err(Into::into)
+    }
+
+    /// Denies an access token until it expires, keyed by its `id` claim
+    pub async fn deny_access_token(&self, token_details: &TokenDetails) -> Result<(), Report> {
+        let mut conn = self.redis.clone();
+        let key = format!("access_denylist:{}", token_details.token_id);
+
+        let ttl = token_details
+            .expires_in
+            .map(|expires_in| expires_in - chrono::Utc::now().timestamp())
+            .unwrap_or(self.auth.access.exp());
+
+        // An expired token is already rejected by its `exp` claim
+        if ttl > 0 {
+            conn.set_ex(&key, 1, ttl as u64).await?;
+        }
 
         Ok(())
     }
 
+    pub async fn is_access_token_denied(&self, token_id: Uuid) -> Result<bool, Report> {
+        let mut conn = self.redis.clone();
+        let key = format!("access_denylist:{}", token_id);
+
+        conn.exists(&key).await.map_err(Into::into)
+    }
+
+    /// The `htu` a DPoP proof must carry for a request to `path`
+    pub fn dpop_htu(&self, path: &str) -> String {
+        let base = self
+            .config
+            .auth()
+            .dpop()
+            .public_url()
+            .map(ToString::to_string)
+            .unwrap_or_else(|| self.config.server().public_url());
+
+        format!("{}{}", base.trim_end_matches('/'), path)
+    }
+
+    /// Validates a DPoP proof and records its `jti` so the same proof can't be replayed
+    pub async fn verify_dpop_proof(
+        &self,
+        proof: &str,
+        method: &str,
+        path: &str,
+        access_token: Option<&str>,
+    ) -> Result<DpopProof, Report> {
+        let max_age = self.config.auth().dpop().proof_max_age();
+        let proof = DpopProof::verify(proof, method, &self.dpop_htu(path), access_token, max_age)?;
+
+        // A proof is accepted while its `iat` is within `max_age` either side of now, so it must
+        // be remembered for twice that
+        let mut conn = self.redis.clone();
+        let key = format!("dpop_jti:{}:{}", proof.jkt, proof.jti);
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::EX(2 * max_age as u64));
+
+        if conn.set_options(&key, 1, options).await?.is_none() {
+            return Err(crate::Error::Auth(AuthError::InvalidDpopProof).into());
+        }
+
+        Ok(proof)
+    }
+
     pub async fn try_from(config: &Config) -> Result<Self, Report> {
         let db = config.database().pool().await;
         let redis = config.redis().multiplexed_connection().await?;
 
+        let token_format = config.auth().token_format();
+
         let auth = AuthContext {
-            access: config.auth().access().try_into()?,
-            refresh: config.auth().refresh().try_into()?,
+            access: TokenIssuer::try_new(token_format, config.auth().access(), "access", &redis)?,
+            refresh: TokenIssuer::try_new(
+                token_format,
+                config.auth().refresh(),
+                "refresh",
+                &redis,
+            )?,
         };
 
+        let mailer = mailer::from_config(config.mailer())?;
+
         Ok(Self {
             redis,
             db,
             auth,
+            mailer,
             config: config.clone(),
         })
     }
@@ -62,19 +678,184 @@ impl AppContext {
 
 #[derive(Clone)]
 pub struct AuthContext {
-    pub access: JwtContext,
-    pub refresh: JwtContext,
+    pub access: TokenIssuer,
+    pub refresh: TokenIssuer,
+}
+
+/// Issues and resolves one kind of token in whichever format `AuthConfig` selects.
+///
+/// Controllers and middlewares only go through this type, so they don't need to know which
+/// mode is active.
+#[derive(Clone)]
+pub enum TokenIssuer {
+    Jwt(JwtContext),
+    Opaque(OpaqueContext),
+}
+
+impl TokenIssuer {
+    pub fn try_new(
+        format: TokenFormat,
+        config: &JwtConfig,
+        kind: &'static str,
+        redis: &MultiplexedConnection,
+    ) -> Result<Self, Report> {
+        Ok(match format {
+            TokenFormat::Jwt => Self::Jwt(config.try_into()?),
+            TokenFormat::Opaque => Self::Opaque(OpaqueContext {
+                redis: redis.clone(),
+                kind,
+                exp: config.exp(),
+            }),
+        })
+    }
+
+    pub fn exp(&self) -> i64 {
+        match self {
+            Self::Jwt(jwt) => jwt.exp,
+            Self::Opaque(opaque) => opaque.exp,
+        }
+    }
+
+    /// Keys to publish for verifying tokens; empty for opaque tokens, which can't be verified
+    /// offline
+    pub fn jwks(&self) -> JwkSet {
+        match self {
+            Self::Jwt(jwt) => jwt.jwks.clone(),
+            Self::Opaque(_) => JwkSet { keys: Vec::new() },
+        }
+    }
+
+    pub async fn generate_token(&self, sub: Uuid) -> Result<TokenDetails, Report> {
+        self.generate_token_with_claims(sub, NoClaims::default())
+            .await
+    }
+
+    pub async fn generate_token_with_claims<C: Serialize>(
+        &self,
+        sub: Uuid,
+        extra: C,
+    ) -> Result<TokenDetails, Report> {
+        match self {
+            Self::Jwt(jwt) => jwt.generate_token_with_claims(sub, extra),
+            Self::Opaque(opaque) => opaque.generate_token_with_claims(sub, extra).await,
+        }
+    }
+
+    pub async fn verify_token(&self, token: &str) -> Result<TokenDetails, Report> {
+        self.verify_token_with_claims::<NoClaims>(token)
+            .await
+            .map(|(token_details, _)| token_details)
+    }
+
+    pub async fn verify_token_with_claims<C: DeserializeOwned + Clone>(
+        &self,
+        token: &str,
+    ) -> Result<(TokenDetails, C), Report> {
+        match self {
+            Self::Jwt(jwt) => jwt.verify_token_with_claims(token),
+            Self::Opaque(opaque) => opaque.verify_token_with_claims(token).await,
+        }
+    }
+}
+
+/// Issues random reference tokens that reveal nothing to the client; the token details live in
+/// Redis under a hash of the token until it expires.
+#[derive(Clone)]
+pub struct OpaqueContext {
+    redis: MultiplexedConnection,
+    /// Keeps access and refresh tokens in separate namespaces so one can't stand in for the
+    /// other
+    kind: &'static str,
+    pub exp: i64,
+}
+
+impl OpaqueContext {
+    fn key(&self, token: &str) -> String {
+        format!("opaque_{}_token:{}", self.kind, token_hash(token))
+    }
+
+    pub async fn generate_token_with_claims<C: Serialize>(
+        &self,
+        sub: Uuid,
+        extra: C,
+    ) -> Result<TokenDetails, Report> {
+        let now = chrono::Utc::now();
+
+        let token = random_token();
+
+        let record = OpaqueTokenRecord {
+            details: TokenDetails {
+                user_pid: sub,
+                token_id: Uuid::new_v4(),
+                expires_in: Some((now + chrono::Duration::seconds(self.exp)).timestamp()),
+                issued_at: Some(now.timestamp()),
+                token: None,
+                family_id: None,
+                jkt: None,
+            },
+            claims: serde_json::to_value(extra)?,
+        };
+
+        let mut conn = self.redis.clone();
+        conn.set_ex(
+            self.key(&token),
+            serde_json::to_string(&record)?,
+            self.exp as u64,
+        )
+        .await?;
+
+        Ok(TokenDetails {
+            token: Some(token),
+            ..record.details
+        })
+    }
+
+    pub async fn verify_token_with_claims<C: DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<(TokenDetails, C), Report> {
+        let mut conn = self.redis.clone();
+
+        let Some(record) = conn.get(self.key(token)).await? else {
+            return Err(crate::Error::Auth(AuthError::InvalidToken).into());
+        };
+
+        let record = serde_json::from_str::<OpaqueTokenRecord>(&record)?;
+        let claims = serde_json::from_value(record.claims)?;
+
+        Ok((record.details, claims))
+    }
 }
 
 #[derive(Clone)]
 pub struct JwtContext {
+    /// Tokens are signed with, and only accepted for, this algorithm
+    pub algorithm: Algorithm,
+    /// `kid` stamped into the header of every token this context signs
+    pub kid: String,
     pub encoding_key: EncodingKey,
-    pub decoding_key: DecodingKey,
+    /// Every key a token may have been signed with, by `kid`
+    pub decoding_keys: HashMap<String, DecodingKey>,
+    /// Public halves of the keys, published through the JWKS endpoint
+    pub jwks: JwkSet,
+    /// Emitted as `iss` and required when verifying
+    pub issuer: String,
+    /// Emitted as `aud`; a token must name at least one of these to verify
+    pub audience: Vec<String>,
     pub exp: i64,
 }
 
 impl JwtContext {
     pub fn generate_token(&self, sub: Uuid) -> Result<TokenDetails, Report> {
+        self.generate_token_with_claims(sub, NoClaims::default())
+    }
+
+    /// Signs a token carrying the application's own claims next to the registered ones
+    pub fn generate_token_with_claims<C: Serialize>(
+        &self,
+        sub: Uuid,
+        extra: C,
+    ) -> Result<TokenDetails, Report> {
         let now = chrono::Utc::now();
 
         let mut token_details = TokenDetails {
@@ -82,17 +863,24 @@ impl JwtContext {
             token_id: Uuid::new_v4(),
             expires_in: Some((now + chrono::Duration::seconds(self.exp)).timestamp()),
             token: None,
+            issued_at: Some(now.timestamp()),
+            family_id: None,
+            jkt: None,
         };
 
         let claims = TokenClaims {
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
             sub: token_details.user_pid.to_string(),
             id: token_details.token_id.to_string(),
             exp: token_details.expires_in.ok_or(crate::Error::TokenError)?,
             iat: now.timestamp(),
             nbf: now.timestamp(),
+            extra,
         };
 
-        let header = Header::new(Algorithm::RS256);
+        let mut header = Header::new(self.algorithm);
+        header.kid = Some(self.kid.clone());
 
         let token = jsonwebtoken::encode(&header, &claims, &self.encoding_key)?;
 
@@ -102,36 +890,406 @@ impl JwtContext {
     }
 
     pub fn verify_token(&self, token: &str) -> Result<TokenDetails, Report> {
-        let validation = Validation::new(Algorithm::RS256);
+        self.verify_token_with_claims::<NoClaims>(token)
+            .map(|(token_details, _)| token_details)
+    }
 
-        let token_data =
-            jsonwebtoken::decode::<TokenClaims>(token, &self.decoding_key, &validation)?;
+    /// Verifies a token and returns the application's own claims alongside its details
+    pub fn verify_token_with_claims<C: DeserializeOwned + Clone>(
+        &self,
+        token: &str,
+    ) -> Result<(TokenDetails, C), Report> {
+        let mut validation = Validation::new(self.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&self.audience);
+        validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud", "sub"]);
+        validation.validate_nbf = true;
 
-        let user_pid = Uuid::parse_str(&token_data.claims.sub)?;
-        let token_id = Uuid::parse_str(&token_data.claims.id)?;
+        // Expired, tampered or foreign tokens are the client's problem, not a server fault
+        let invalid = || crate::Error::Auth(AuthError::InvalidToken);
 
-        Ok(TokenDetails {
+        // Tokens issued before keys had ids carry no `kid`; they were signed by the signing key
+        let header = jsonwebtoken::decode_header(token).map_err(|_| invalid())?;
+        let kid = header.kid.as_deref().unwrap_or(&self.kid);
+
+        let decoding_key = self.decoding_keys.get(kid).ok_or_else(invalid)?;
+
+        let token_data = jsonwebtoken::decode::<TokenClaims<C>>(token, decoding_key, &validation)
+            .map_err(|_| invalid())?;
+
+        let user_pid = Uuid::parse_str(&token_data.claims.sub).map_err(|_| invalid())?;
+        let token_id = Uuid::parse_str(&token_data.claims.id).map_err(|_| invalid())?;
+
+        let token_details = TokenDetails {
             token: None,
             token_id,
             user_pid,
-            expires_in: None,
-        })
+            expires_in: Some(token_data.claims.exp),
+            issued_at: Some(token_data.claims.iat),
+            family_id: None,
+            jkt: None,
+        };
+
+        Ok((token_details, token_data.claims.extra))
     }
 }
 
-impl TryFrom<&RsaJwtConfig> for JwtContext {
+impl TryFrom<&JwtConfig> for JwtContext {
     type Error = Report;
 
-    fn try_from(config: &RsaJwtConfig) -> Result<Self, Self::Error> {
-        let encoding_key = config.encoding_key()?;
-        let decoding_key = config.decoding_key()?;
+    fn try_from(config: &JwtConfig) -> Result<Self, Self::Error> {
+        let algorithm = config.algorithm();
+        let signing_key = config.signing_key()?;
+        let encoding_key = signing_key.encoding_key(algorithm)?;
+
+        let mut decoding_keys = HashMap::new();
+        let mut jwks = JwkSet { keys: Vec::new() };
+
+        for key in config.keys() {
+            decoding_keys.insert(key.kid().to_string(), key.decoding_key(algorithm)?);
+
+            if let Some(jwk) = key.public_jwk(algorithm)? {
+                jwks.keys.push(jwk);
+            }
+        }
 
         let exp = config.exp();
 
         Ok(Self {
+            issuer: config.issuer().to_string(),
+            audience: config.audience().to_vec(),
+            algorithm,
+            kid: signing_key.kid().to_string(),
             encoding_key,
-            decoding_key,
+            decoding_keys,
+            jwks,
             exp,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, response::IntoResponse};
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::cli::keys::{GeneratedKey, generate_key};
+
+    const SECRET: &[u8] = b"a-test-secret-that-is-long-enough-for-hs256";
+    const ISSUER: &str = "https://auth.example.com";
+
+    /// Freshly generated key material, configured inline
+    struct TestKey {
+        kid: &'static str,
+        generated: GeneratedKey,
+    }
+
+    impl TestKey {
+        fn new(kid: &'static str, algorithm: Algorithm) -> Self {
+            Self {
+                kid,
+                generated: generate_key(algorithm).unwrap(),
+            }
+        }
+
+        fn signing(&self) -> Value {
+            match &self.generated.public {
+                Some(public) => json!({
+                    "kid": self.kid,
+                    "private_key_pem": self.generated.private,
+                    "public_key_pem": public,
+                }),
+                None => json!({"kid": self.kid, "secret_value": self.generated.private}),
+            }
+        }
+
+        /// The key as kept after a rotation; a shared secret can't be split
+        fn verify_only(&self) -> Value {
+            match &self.generated.public {
+                Some(public) => json!({"kid": self.kid, "public_key_pem": public}),
+                None => self.signing(),
+            }
+        }
+    }
+
+    fn jwt_context(
+        algorithm: Algorithm,
+        signing_key: &str,
+        keys: Vec<Value>,
+        audience: &[&str],
+    ) -> JwtContext {
+        let config = serde_json::from_value::<JwtConfig>(json!({
+            "algorithm": algorithm,
+            "signing_key": signing_key,
+            "keys": keys,
+            "issuer": ISSUER,
+            "audience": audience,
+            "exp": 900,
+        }))
+        .unwrap();
+
+        JwtContext::try_from(&config).unwrap()
+    }
+
+    fn token(jwt: &JwtContext) -> String {
+        jwt.generate_token(Uuid::new_v4()).unwrap().token.unwrap()
+    }
+
+    /// Signs like tokens issued before keys had ids
+    fn token_without_kid(jwt: &JwtContext) -> String {
+        let now = chrono::Utc::now().timestamp();
+        let claims = TokenClaims {
+            iss: jwt.issuer.clone(),
+            aud: jwt.audience.clone(),
+            sub: Uuid::new_v4().to_string(),
+            id: Uuid::new_v4().to_string(),
+            exp: now + jwt.exp,
+            iat: now,
+            nbf: now,
+            extra: NoClaims::default(),
+        };
+
+        jsonwebtoken::encode(&Header::new(jwt.algorithm), &claims, &jwt.encoding_key).unwrap()
+    }
+
+    fn hs256_context(audience: &str) -> JwtContext {
+        JwtContext {
+            algorithm: Algorithm::HS256,
+            kid: "test".to_string(),
+            encoding_key: EncodingKey::from_secret(SECRET),
+            decoding_keys: HashMap::from([("test".to_string(), DecodingKey::from_secret(SECRET))]),
+            jwks: JwkSet { keys: Vec::new() },
+            issuer: "https://auth.example.com".to_string(),
+            audience: vec![audience.to_string()],
+            exp: 900,
+        }
+    }
+
+    fn assert_unauthorized(result: Result<TokenDetails, Report>) {
+        let err = result.expect_err("token should be rejected");
+
+        assert!(matches!(
+            err.0.downcast_ref::<crate::Error>(),
+            Some(crate::Error::Auth(AuthError::InvalidToken))
+        ));
+        assert_eq!(err.into_response().status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn expired_token_is_unauthorized() {
+        // Past the default 60 seconds of leeway
+        let jwt = JwtContext {
+            exp: -120,
+            ..hs256_context("api")
+        };
+        let token = jwt.generate_token(Uuid::new_v4()).unwrap().token.unwrap();
+
+        assert_unauthorized(jwt.verify_token(&token));
+    }
+
+    #[test]
+    fn token_for_another_audience_is_unauthorized() {
+        let token = hs256_context("another-api")
+            .generate_token(Uuid::new_v4())
+            .unwrap()
+            .token
+            .unwrap();
+
+        assert_unauthorized(hs256_context("api").verify_token(&token));
+    }
+
+    #[test]
+    fn malformed_token_is_unauthorized() {
+        assert_unauthorized(hs256_context("api").verify_token("not-a-jwt"));
+    }
+
+    #[test]
+    fn stamps_the_signing_kid() {
+        let key = TestKey::new("2025-01", Algorithm::ES256);
+        let jwt = jwt_context(Algorithm::ES256, key.kid, vec![key.signing()], &["api"]);
+
+        let header = jsonwebtoken::decode_header(token(&jwt)).unwrap();
+
+        assert_eq!(header.kid.as_deref(), Some("2025-01"));
+    }
+
+    #[test]
+    fn verifies_tokens_of_a_rotated_out_key_until_it_is_removed() {
+        let old = TestKey::new("2024-01", Algorithm::ES256);
+        let new = TestKey::new("2025-01", Algorithm::ES256);
+
+        let before = jwt_context(Algorithm::ES256, old.kid, vec![old.signing()], &["api"]);
+        let old_token = before.generate_token(Uuid::new_v4()).unwrap();
+
+        let after = jwt_context(
+            Algorithm::ES256,
+            new.kid,
+            vec![new.signing(), old.verify_only()],
+            &["api"],
+        );
+        let verified = after
+            .verify_token(old_token.token.as_deref().unwrap())
+            .unwrap();
+        assert_eq!(verified.user_pid, old_token.user_pid);
+        assert_eq!(verified.token_id, old_token.token_id);
+        assert!(after.verify_token(&token(&after)).is_ok());
+
+        let kids = after
+            .jwks
+            .keys
+            .iter()
+            .filter_map(|jwk| jwk.common.key_id.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(kids, ["2025-01", "2024-01"]);
+
+        let removed = jwt_context(Algorithm::ES256, new.kid, vec![new.signing()], &["api"]);
+        assert_unauthorized(removed.verify_token(old_token.token.as_deref().unwrap()));
+    }
+
+    #[test]
+    fn rejects_a_kid_signed_by_another_key() {
+        let key = TestKey::new("shared-kid", Algorithm::ES256);
+        let impostor = TestKey::new("shared-kid", Algorithm::ES256);
+
+        let jwt = jwt_context(Algorithm::ES256, key.kid, vec![key.signing()], &["api"]);
+        let forged = jwt_context(
+            Algorithm::ES256,
+            impostor.kid,
+            vec![impostor.signing()],
+            &["api"],
+        );
+
+        assert_unauthorized(jwt.verify_token(&token(&forged)));
+    }
+
+    #[test]
+    fn verifies_tokens_without_kid_with_the_signing_key() {
+        let old = TestKey::new("2024-01", Algorithm::ES256);
+        let new = TestKey::new("2025-01", Algorithm::ES256);
+
+        let jwt = jwt_context(Algorithm::ES256, old.kid, vec![old.signing()], &["api"]);
+        let legacy = token_without_kid(&jwt);
+        assert!(jwt.verify_token(&legacy).is_ok());
+
+        // Once the signing key changes, only a `kid` could point at the old one
+        let rotated = jwt_context(
+            Algorithm::ES256,
+            new.kid,
+            vec![new.signing(), old.verify_only()],
+            &["api"],
+        );
+        assert_unauthorized(rotated.verify_token(&legacy));
+    }
+
+    #[test]
+    fn signs_and_verifies_with_every_key_family() {
+        for algorithm in [
+            Algorithm::RS256,
+            Algorithm::ES256,
+            Algorithm::ES384,
+            Algorithm::EdDSA,
+            Algorithm::HS256,
+        ] {
+            let key = TestKey::new("k1", algorithm);
+            let jwt = jwt_context(algorithm, key.kid, vec![key.signing()], &["api"]);
+
+            let issued = jwt.generate_token(Uuid::new_v4()).unwrap();
+            let token = issued.token.as_deref().unwrap();
+            assert_eq!(jsonwebtoken::decode_header(token).unwrap().alg, algorithm);
+
+            let verified = jwt.verify_token(token).unwrap();
+            assert_eq!(verified.user_pid, issued.user_pid, "{algorithm:?}");
+            assert_eq!(verified.token_id, issued.token_id, "{algorithm:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_tokens_signed_with_another_algorithm() {
+        let es256 = TestKey::new("k1", Algorithm::ES256);
+        let hs256 = TestKey::new("k1", Algorithm::HS256);
+
+        let jwt = jwt_context(Algorithm::ES256, es256.kid, vec![es256.signing()], &["api"]);
+        let other = jwt_context(Algorithm::HS256, hs256.kid, vec![hs256.signing()], &["api"]);
+
+        assert_unauthorized(jwt.verify_token(&token(&other)));
+    }
+
+    #[test]
+    fn publishes_only_public_keys() {
+        let ed = TestKey::new("ed", Algorithm::EdDSA);
+        let jwt = jwt_context(Algorithm::EdDSA, ed.kid, vec![ed.signing()], &["api"]);
+        assert_eq!(jwt.jwks.keys.len(), 1);
+
+        let hmac = TestKey::new("hmac", Algorithm::HS256);
+        let jwt = jwt_context(Algorithm::HS256, hmac.kid, vec![hmac.signing()], &["api"]);
+        assert!(jwt.jwks.keys.is_empty());
+    }
+
+    #[test]
+    fn rejects_another_issuer() {
+        let key = TestKey::new("k1", Algorithm::ES256);
+        let jwt = jwt_context(Algorithm::ES256, key.kid, vec![key.signing()], &["api"]);
+        let other = JwtContext {
+            issuer: "https://other.example.com".to_string(),
+            ..jwt.clone()
+        };
+
+        assert_unauthorized(jwt.verify_token(&token(&other)));
+    }
+
+    #[test]
+    fn accepts_a_token_naming_any_expected_audience() {
+        let key = TestKey::new("k1", Algorithm::ES256);
+        let jwt = jwt_context(
+            Algorithm::ES256,
+            key.kid,
+            vec![key.signing()],
+            &["api", "admin"],
+        );
+
+        for audience in [&["admin"][..], &["billing", "api"]] {
+            let issuer = JwtContext {
+                audience: audience.iter().map(ToString::to_string).collect(),
+                ..jwt.clone()
+            };
+
+            assert!(jwt.verify_token(&token(&issuer)).is_ok(), "{audience:?}");
+        }
+
+        let billing = JwtContext {
+            audience: vec!["billing".to_string()],
+            ..jwt.clone()
+        };
+        assert_unauthorized(jwt.verify_token(&token(&billing)));
+    }
+
+    #[test]
+    fn carries_custom_claims() {
+        #[derive(Debug, Clone, PartialEq, Serialize, serde::Deserialize)]
+        struct AppClaims {
+            role: String,
+            tenant: u32,
+        }
+
+        let key = TestKey::new("k1", Algorithm::ES256);
+        let jwt = jwt_context(Algorithm::ES256, key.kid, vec![key.signing()], &["api"]);
+        let claims = AppClaims {
+            role: "admin".to_string(),
+            tenant: 7,
+        };
+
+        let token = jwt
+            .generate_token_with_claims(Uuid::new_v4(), claims.clone())
+            .unwrap()
+            .token
+            .unwrap();
+
+        let (_, verified) = jwt.verify_token_with_claims::<AppClaims>(&token).unwrap();
+        assert_eq!(verified, claims);
+
+        // Claims the token doesn't carry fail to verify rather than default silently
+        let plain = jwt.generate_token(Uuid::new_v4()).unwrap().token.unwrap();
+        assert!(jwt.verify_token_with_claims::<AppClaims>(&plain).is_err());
+    }
+}
@@ -4,30 +4,64 @@ use axum::{
     Extension, Json, Router,
     body::Body,
     debug_handler,
-    extract::State,
+    extract::{OriginalUri, Path, Query, State},
     http::{
-        HeaderValue, StatusCode,
-        header::{AUTHORIZATION, SET_COOKIE},
+        HeaderMap, HeaderValue, Method, StatusCode,
+        header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_DISPOSITION, SET_COOKIE},
     },
     response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, patch, post},
 };
-use axum_extra::extract::cookie;
+use axum_extra::{TypedHeader, extract::cookie, headers::Cookie};
 use serde_json::json;
+use uuid::Uuid;
 
 use crate::{
     Result,
+    config::AuthMode,
     context::AppContext,
-    middlewares::{AuthError, AuthLayer, RefreshLayer},
-    models::{LoginUser, RegisterUser, User, token::TokenDetails},
+    mailer::{Email, templates},
+    middlewares::{
+        AuthError, AuthLayer,
+        auth::{DPOP, SESSION_COOKIE},
+    },
+    models::{
+        LoginUser, ModelError, RegisterUser, User,
+        dpop::{DpopClaims, DpopProof},
+        export,
+        session::ClientInfo,
+        token::{RefreshParams, TokenDetails},
+        users::{
+            ChangePasswordParams, DeleteAccountParams, EmailChange, EmailConfirmation, EmailParams,
+            ResetPasswordParams, TokenParams, UpdateProfileParams,
+        },
+        validation::JsonBody,
+    },
 };
 
+/// One-time token purpose of email verification links
+const VERIFY_EMAIL: &str = "verify_email";
+
+/// One-time token purpose of password reset links
+const RESET_PASSWORD: &str = "reset_password";
+
+/// One-time token purpose of links confirming a new email address
+const CHANGE_EMAIL: &str = "change_email";
+
 #[debug_handler]
 async fn register(
     State(ctx): State<Arc<AppContext>>,
-    Json(params): Json<RegisterUser<'static>>,
+    JsonBody(params): JsonBody<RegisterUser<'static>>,
 ) -> Result<Response> {
-    let _new_user = User::create_user(&ctx.db, &params).await?;
+    params.validate(ctx.config.auth().password())?;
+
+    let new_user = User::create_user(&ctx.db, &params).await?;
+
+    // The account exists either way; a failed email can be sent again through
+    // `/resend-verification`
+    if let Err(err) = send_verification_email(&ctx, &new_user).await {
+        tracing::error!(user_pid = %new_user.pid(), "Failed to send verification email: {}", err);
+    }
 
     Ok((
         StatusCode::CREATED,
@@ -38,49 +72,380 @@ async fn register(
         .into_response())
 }
 
+/// Sends an email the request shouldn't fail over, such as a notice; a failure is only logged
+async fn send_best_effort(ctx: &AppContext, email: &Email) {
+    if let Err(err) = ctx.mailer.send(email).await {
+        tracing::error!(to = %email.to, subject = %email.subject, "Failed to send email: {}", err);
+    }
+}
+
+/// Emails `user` a single-use link confirming their current address
+async fn send_verification_email(ctx: &AppContext, user: &User) -> Result<()> {
+    let token_exp = ctx.config.auth().email_verification().token_exp();
+
+    let confirmation = EmailConfirmation {
+        user_pid: user.pid(),
+        email: user.email().to_string(),
+    };
+    let token = ctx
+        .issue_one_time_token(VERIFY_EMAIL, &confirmation, token_exp)
+        .await?;
+
+    let link = format!(
+        "{}/auth/verify-email?token={}",
+        ctx.config.server().public_url(),
+        token
+    );
+
+    ctx.mailer
+        .send(&templates::verify_email(
+            user.email(),
+            user.name(),
+            &link,
+            token_exp,
+        ))
+        .await
+}
+
+/// Target of the link in the verification email
+#[debug_handler]
+async fn verify_email(
+    State(ctx): State<Arc<AppContext>>,
+    Query(params): Query<TokenParams>,
+) -> Result<Response> {
+    let confirmation = ctx
+        .consume_one_time_token::<EmailConfirmation>(VERIFY_EMAIL, &params.token)
+        .await?
+        .ok_or(crate::Error::Auth(AuthError::InvalidOneTimeToken))?;
+
+    let user = User::find_by_pid(&ctx.db, confirmation.user_pid).await?;
+
+    if user.email() != confirmation.email {
+        return Err(crate::Error::Auth(AuthError::InvalidOneTimeToken).into());
+    }
+
+    user.mark_email_verified(&ctx.db).await?;
+
+    Ok((StatusCode::OK, Json(json!({"message": "Email verified"}))).into_response())
+}
+
+/// Sends a new verification link. Always answers `202 Accepted`, so it can't be used to find
+/// out which addresses have accounts.
+#[debug_handler]
+async fn resend_verification(
+    State(ctx): State<Arc<AppContext>>,
+    Json(params): Json<EmailParams>,
+) -> Result<Response> {
+    let accepted = (
+        StatusCode::ACCEPTED,
+        Json(json!({"message": "If the account needs verifying, an email is on its way"})),
+    );
+
+    let Some(user) = User::find_by_email(&ctx.db, &params.email).await? else {
+        return Ok(accepted.into_response());
+    };
+
+    if user.is_email_verified() {
+        return Ok(accepted.into_response());
+    }
+
+    // Sent in the background, so that an unverified account is answered as fast as any other
+    // address
+    tokio::spawn(async move {
+        if let Err(err) = resend_verification_email(&ctx, &user).await {
+            tracing::error!(user_pid = %user.pid(), "Failed to send verification email: {}", err);
+        }
+    });
+
+    Ok(accepted.into_response())
+}
+
+/// Sends `user` a new verification link, at most once a minute
+async fn resend_verification_email(ctx: &AppContext, user: &User) -> Result<()> {
+    // One email a minute is plenty, and keeps this from being used to flood an inbox
+    if !ctx
+        .try_cooldown(&format!("verification_email_sent:{}", user.pid()), 60)
+        .await?
+    {
+        return Ok(());
+    }
+
+    send_verification_email(ctx, user).await
+}
+
+/// Emails a single-use password reset link. Always answers `202 Accepted`, so it can't be used
+/// to find out which addresses have accounts.
+#[debug_handler]
+async fn forgot_password(
+    State(ctx): State<Arc<AppContext>>,
+    Json(params): Json<EmailParams>,
+) -> Result<Response> {
+    let accepted = (
+        StatusCode::ACCEPTED,
+        Json(json!({"message": "If an account uses this address, a reset link is on its way"})),
+    );
+
+    let Some(user) = User::find_by_email(&ctx.db, &params.email).await? else {
+        return Ok(accepted.into_response());
+    };
+
+    // Sent in the background, so that a known address is answered as fast as an unknown one and
+    // a failure looks the same too
+    tokio::spawn(async move {
+        if let Err(err) = send_reset_email(&ctx, &user).await {
+            tracing::error!(user_pid = %user.pid(), "Failed to send password reset email: {}", err);
+        }
+    });
+
+    Ok(accepted.into_response())
+}
+
+/// Emails `user` a single-use password reset link, at most once a minute
+async fn send_reset_email(ctx: &AppContext, user: &User) -> Result<()> {
+    if !ctx
+        .try_cooldown(&format!("reset_email_sent:{}", user.pid()), 60)
+        .await?
+    {
+        return Ok(());
+    }
+
+    let reset = ctx.config.auth().password_reset();
+    let token_exp = reset.token_exp();
+
+    let confirmation = EmailConfirmation {
+        user_pid: user.pid(),
+        email: user.email().to_string(),
+    };
+    let token = ctx
+        .issue_one_time_token(RESET_PASSWORD, &confirmation, token_exp)
+        .await?;
+
+    // The link is opened by the client app, which posts the token with the new password
+    let link = reset.link(&token);
+
+    ctx.mailer
+        .send(&templates::reset_password(
+            user.email(),
+            user.name(),
+            &link,
+            token_exp,
+        ))
+        .await
+}
+
+/// Sets a new password with a token from a reset email, and signs the user out everywhere
+#[debug_handler]
+async fn reset_password(
+    State(ctx): State<Arc<AppContext>>,
+    Json(params): Json<ResetPasswordParams>,
+) -> Result<Response> {
+    // Checked first so a rejected password doesn't use up the token
+    params.validate(ctx.config.auth().password())?;
+
+    let confirmation = ctx
+        .consume_one_time_token::<EmailConfirmation>(RESET_PASSWORD, &params.token)
+        .await?
+        .ok_or(crate::Error::Auth(AuthError::InvalidOneTimeToken))?;
+
+    let user = User::find_by_pid(&ctx.db, confirmation.user_pid).await?;
+
+    if user.email() != confirmation.email {
+        return Err(crate::Error::Auth(AuthError::InvalidOneTimeToken).into());
+    }
+
+    user.update_password(&ctx.db, &params.password).await?;
+
+    // Whoever knew the old password, or held a token, is locked out
+    ctx.revoke_user_sessions(user.pid(), None).await?;
+
+    tracing::info!(user_pid = %user.pid(), "Password reset");
+
+    send_best_effort(
+        &ctx,
+        &templates::password_changed(user.email(), user.name()),
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(json!({"message": "Password reset"}))).into_response())
+}
+
+/// Validates the optional `DPoP` header of a token request; its key thumbprint is what the
+/// issued tokens get bound to
+async fn dpop_proof(
+    ctx: &AppContext,
+    headers: &HeaderMap,
+    method: &Method,
+    uri: &OriginalUri,
+) -> Result<Option<DpopProof>> {
+    let Some(proof) = headers.get(DPOP) else {
+        return Ok(None);
+    };
+
+    let proof = proof
+        .to_str()
+        .map_err(|_| crate::Error::Auth(AuthError::InvalidDpopProof))?;
+
+    ctx.verify_dpop_proof(proof, method.as_str(), uri.path(), None)
+        .await
+        .map(Some)
+}
+
 #[debug_handler]
 async fn login(
     State(ctx): State<Arc<AppContext>>,
-    Json(params): Json<LoginUser<'static>>,
+    method: Method,
+    uri: OriginalUri,
+    headers: HeaderMap,
+    client: ClientInfo,
+    JsonBody(params): JsonBody<LoginUser<'static>>,
 ) -> Result<Response> {
+    params.validate(ctx.config.auth().password())?;
+
     let user = User::find_by_email(&ctx.db, params.email())
         .await?
         .ok_or(crate::Error::Auth(AuthError::WrongCredentials))?;
 
     user.verify_password(params.password())?;
 
-    // issue access & refresh tokens
-    let access_token = ctx.auth.access.generate_token(user.pid())?;
-    let refresh_token = ctx.auth.refresh.generate_token(user.pid())?;
+    if ctx.config.auth().email_verification().required() && !user.is_email_verified() {
+        return Err(crate::Error::Auth(AuthError::EmailNotVerified).into());
+    }
+
+    if ctx.config.auth().mode() == AuthMode::Session {
+        ctx.reserve_session_slot(user.pid()).await?;
+
+        let session = ctx.start_cookie_session(user.pid(), &client).await?;
+
+        let body = json!({
+            "name": user.name(),
+            "created_at": user.created_at().to_string()
+        });
+
+        return session_response(&ctx, body, &session);
+    }
+
+    let proof = dpop_proof(&ctx, &headers, &method, &uri).await?;
+    let jkt = proof.as_ref().map(|proof| proof.jkt.as_str());
+
+    // issue refresh & access tokens, bound to the DPoP key if the client sent a proof
+    let mut refresh_token = ctx
+        .auth
+        .refresh
+        .generate_token_with_claims(user.pid(), DpopClaims::bound_to(jkt))
+        .await?;
+    // Every login starts a new refresh token family
+    refresh_token.family_id = Some(Uuid::new_v4());
+    refresh_token.jkt = jkt.map(ToString::to_string);
+
+    // Only once nothing else can fail, so a rejected login never evicts another session
+    ctx.reserve_session_slot(user.pid()).await?;
 
     ctx.store_refresh_token(&refresh_token).await?;
+    ctx.start_session(&refresh_token, &client).await?;
+
+    let access_token = ctx.issue_access_token(&refresh_token).await?;
 
-    let access_token = access_token.token.unwrap();
-    let refresh_token = refresh_token.token.unwrap();
+    // The refresh token is also in the body for clients that don't keep cookies
+    let body = json!({
+        "access_token": access_token.token,
+        "refresh_token": refresh_token.token,
+        "token_type": token_type(jkt),
+        "name": user.name(),
+        "created_at": user.created_at().to_string()
+    });
+
+    token_response(&ctx, body, &access_token, &refresh_token)
+}
 
-    let access_cookie = cookie::Cookie::build(("access_token", &access_token))
+/// Exchanges a refresh token, taken from the JSON body or the `refresh_token` cookie, for a new
+/// access token and a rotated refresh token.
+#[debug_handler]
+async fn refresh(
+    State(ctx): State<Arc<AppContext>>,
+    method: Method,
+    uri: OriginalUri,
+    headers: HeaderMap,
+    client: ClientInfo,
+    cookies: Option<TypedHeader<Cookie>>,
+    params: Option<Json<RefreshParams>>,
+) -> Result<Response> {
+    let proof = dpop_proof(&ctx, &headers, &method, &uri).await?;
+
+    let refresh_token = params
+        .map(|Json(params)| params.refresh_token)
+        .or_else(|| {
+            cookies.and_then(|TypedHeader(cookies)| {
+                cookies.get("refresh_token").map(ToString::to_string)
+            })
+        })
+        .ok_or(crate::Error::Auth(AuthError::MissingCredentials))?;
+
+    let presented = ctx
+        .auth
+        .refresh
+        .verify_token(&refresh_token)
+        .await
+        .map_err(|_| crate::Error::Auth(AuthError::InvalidToken))?;
+
+    let refresh_token = ctx
+        .rotate_refresh_token(
+            &presented,
+            proof.as_ref().map(|proof| proof.jkt.as_str()),
+            &client,
+        )
+        .await?;
+    // The refresh token decides the binding, so a bound session can't be downgraded to Bearer
+    let jkt = refresh_token.jkt.as_deref();
+    let access_token = ctx.issue_access_token(&refresh_token).await?;
+
+    let body = json!({
+        "access_token": access_token.token,
+        "refresh_token": refresh_token.token,
+        "token_type": token_type(jkt),
+        "expires_in": ctx.auth.access.exp()
+    });
+
+    token_response(&ctx, body, &access_token, &refresh_token)
+}
+
+fn token_type(jkt: Option<&str>) -> &'static str {
+    if jkt.is_some() { "DPoP" } else { "Bearer" }
+}
+
+/// Builds a `200 OK` response carrying `body` and sets both tokens as cookies
+fn token_response(
+    ctx: &AppContext,
+    body: serde_json::Value,
+    access_token: &TokenDetails,
+    refresh_token: &TokenDetails,
+) -> Result<Response> {
+    let access_token = access_token
+        .token
+        .as_deref()
+        .ok_or(crate::Error::Auth(AuthError::TokenCreation))?;
+    let refresh_token = refresh_token
+        .token
+        .as_deref()
+        .ok_or(crate::Error::Auth(AuthError::TokenCreation))?;
+
+    let access_cookie = cookie::Cookie::build(("access_token", access_token))
         .path("/")
         .http_only(false)
-        .max_age(time::Duration::seconds(ctx.auth.access.exp))
+        .max_age(time::Duration::seconds(ctx.auth.access.exp()))
         .same_site(cookie::SameSite::Lax);
 
-    let refresh_cookie = cookie::Cookie::build(("refresh_token", &refresh_token))
+    let refresh_cookie = cookie::Cookie::build(("refresh_token", refresh_token))
         .path("/")
         .http_only(true)
-        .max_age(time::Duration::seconds(ctx.auth.refresh.exp))
+        .max_age(time::Duration::seconds(ctx.auth.refresh.exp()))
         .same_site(cookie::SameSite::Lax);
 
-    let mut res = Response::builder().status(StatusCode::OK).body(Body::from(
-        json!({
-            "access_token": &access_token,
-            "name": user.name(),
-            "created_at": user.created_at().to_string()
-        })
-        .to_string(),
-    ))?;
+    let mut res = Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(body.to_string()))?;
 
     res.headers_mut()
-        .append(AUTHORIZATION, HeaderValue::from_str(access_token.as_str())?);
+        .append(AUTHORIZATION, HeaderValue::from_str(access_token)?);
     res.headers_mut().append(
         SET_COOKIE,
         HeaderValue::from_str(access_cookie.to_string().as_str())?,
@@ -93,6 +458,30 @@ async fn login(
     Ok(res)
 }
 
+/// Builds a `200 OK` response carrying `body` and sets the server-side session cookie.
+///
+/// The `__Host-` prefix makes browsers insist on `Secure`, `Path=/` and no `Domain`, so the
+/// cookie can't be planted by a sibling subdomain.
+fn session_response(ctx: &AppContext, body: serde_json::Value, session: &str) -> Result<Response> {
+    let session_cookie = cookie::Cookie::build((SESSION_COOKIE, session))
+        .path("/")
+        .secure(true)
+        .http_only(true)
+        .max_age(time::Duration::seconds(ctx.auth.refresh.exp()))
+        .same_site(cookie::SameSite::Lax);
+
+    let mut res = Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(body.to_string()))?;
+
+    res.headers_mut().append(
+        SET_COOKIE,
+        HeaderValue::from_str(session_cookie.to_string().as_str())?,
+    );
+
+    Ok(res)
+}
+
 #[debug_handler]
 async fn current(
     Extension(auth): Extension<TokenDetails>,
@@ -111,29 +500,329 @@ async fn current(
         .into_response())
 }
 
+/// Updates the profile of the signed-in user.
+///
+/// A new name applies right away. A new email address only replaces the current one once the
+/// link sent to it is opened; the current address gets a notice so a hijacked session can't
+/// quietly take over the account. An address another account already uses is refused with
+/// `409 Conflict`, and then nothing is changed.
+#[debug_handler]
+async fn update_profile(
+    Extension(auth): Extension<TokenDetails>,
+    State(ctx): State<Arc<AppContext>>,
+    Json(params): Json<UpdateProfileParams>,
+) -> Result<Response> {
+    params.validate()?;
+
+    let mut user = User::find_by_pid(&ctx.db, auth.user_pid).await?;
+
+    let pending_email = params
+        .email
+        .as_deref()
+        .map(str::trim)
+        .filter(|email| *email != user.email());
+
+    // The confirmation goes out before anything is saved, so a failure changes nothing
+    if let Some(new_email) = pending_email {
+        // Otherwise the link could only fail once it's followed
+        if User::find_by_email(&ctx.db, new_email).await?.is_some() {
+            return Err(crate::Error::Model(ModelError::EntityAlreadyExists).into());
+        }
+
+        let token_exp = ctx.config.auth().email_verification().token_exp();
+
+        let change = EmailChange {
+            user_pid: user.pid(),
+            old_email: user.email().to_string(),
+            new_email: new_email.to_string(),
+        };
+        let token = ctx
+            .issue_one_time_token(CHANGE_EMAIL, &change, token_exp)
+            .await?;
+
+        let link = format!(
+            "{}/auth/confirm-email-change?token={}",
+            ctx.config.server().public_url(),
+            token
+        );
+
+        let name = params.name.as_deref().map_or(user.name(), str::trim);
+
+        ctx.mailer
+            .send(&templates::confirm_email_change(
+                new_email, name, &link, token_exp,
+            ))
+            .await?;
+    }
+
+    if let Some(name) = &params.name {
+        user = user.update_name(&ctx.db, name).await?;
+    }
+
+    if let Some(new_email) = pending_email {
+        send_best_effort(
+            &ctx,
+            &templates::email_change_requested(user.email(), user.name(), new_email),
+        )
+        .await;
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "name": user.name(),
+            "pid": user.pid(),
+            "email": user.email(),
+            "pending_email": pending_email
+        })),
+    )
+        .into_response())
+}
+
+/// Downloads everything the service holds about the signed-in user as one JSON document
+#[debug_handler]
+async fn export_data(
+    Extension(auth): Extension<TokenDetails>,
+    State(ctx): State<Arc<AppContext>>,
+) -> Result<Response> {
+    let archive = export::export_user_data(&ctx, auth.user_pid).await?;
+
+    Ok((
+        StatusCode::OK,
+        [
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"export-{}.json\"", auth.user_pid),
+            ),
+            (CACHE_CONTROL, "no-store".to_string()),
+        ],
+        Json(archive),
+    )
+        .into_response())
+}
+
+/// Deletes the signed-in user's account and signs it out everywhere.
+///
+/// The password is required unless the session signed in within the last `reauth_window`
+/// seconds, so an unattended or stolen session can't delete the account.
+#[debug_handler]
+async fn delete_account(
+    Extension(auth): Extension<TokenDetails>,
+    State(ctx): State<Arc<AppContext>>,
+    params: Option<Json<DeleteAccountParams>>,
+) -> Result<Response> {
+    let Json(params) = params.unwrap_or_default();
+    let config = ctx.config.auth().account_deletion();
+
+    let user = User::find_by_pid(&ctx.db, auth.user_pid).await?;
+
+    if let Some(password) = &params.password {
+        user.verify_password(password)?;
+    } else {
+        let signed_in_at = match auth.family_id {
+            Some(sid) => ctx
+                .find_session(sid)
+                .await?
+                .map(|session| session.created_at),
+            None => None,
+        };
+
+        let recent = signed_in_at.is_some_and(|signed_in_at| {
+            (chrono::Utc::now() - signed_in_at).num_seconds() <= config.reauth_window()
+        });
+
+        if !recent {
+            return Err(crate::Error::Auth(AuthError::ReauthenticationRequired).into());
+        }
+    }
+
+    user.soft_delete(&ctx.db).await?;
+
+    ctx.revoke_user_sessions(user.pid(), None).await?;
+    deny_request_token(&ctx, &auth).await?;
+
+    tracing::info!(user_pid = %user.pid(), "Account deleted");
+
+    send_best_effort(
+        &ctx,
+        &templates::account_deleted(user.email(), user.name(), config.grace_period()),
+    )
+    .await;
+
+    clear_cookies_response(&ctx, json!({"message": "Account deleted"}))
+}
+
+/// Target of the link sent to a new email address; swaps it in for the old one
+#[debug_handler]
+async fn confirm_email_change(
+    State(ctx): State<Arc<AppContext>>,
+    Query(params): Query<TokenParams>,
+) -> Result<Response> {
+    let change = ctx
+        .consume_one_time_token::<EmailChange>(CHANGE_EMAIL, &params.token)
+        .await?
+        .ok_or(crate::Error::Auth(AuthError::InvalidOneTimeToken))?;
+
+    let user = User::find_by_pid(&ctx.db, change.user_pid).await?;
+
+    // The address changed some other way since the link was sent
+    if user.email() != change.old_email {
+        return Err(crate::Error::Auth(AuthError::InvalidOneTimeToken).into());
+    }
+
+    // Opening the link proves the new address works, so it counts as verified
+    let user = user.update_email(&ctx.db, &change.new_email).await?;
+
+    tracing::info!(user_pid = %user.pid(), "Email address changed");
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({"message": "Email address changed"})),
+    )
+        .into_response())
+}
+
+/// Changes the password of the signed-in user and signs out every other session
+#[debug_handler]
+async fn change_password(
+    Extension(auth): Extension<TokenDetails>,
+    State(ctx): State<Arc<AppContext>>,
+    Json(params): Json<ChangePasswordParams>,
+) -> Result<Response> {
+    params.validate(ctx.config.auth().password())?;
+
+    let user = User::find_by_pid(&ctx.db, auth.user_pid).await?;
+
+    user.verify_password(&params.current_password)?;
+    user.update_password(&ctx.db, &params.new_password).await?;
+
+    // The session making the change stays signed in; access tokens of the others stop working
+    // with their sessions
+    ctx.revoke_user_sessions(user.pid(), auth.family_id).await?;
+
+    tracing::info!(user_pid = %user.pid(), "Password changed");
+
+    send_best_effort(
+        &ctx,
+        &templates::password_changed(user.email(), user.name()),
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(json!({"message": "Password changed"}))).into_response())
+}
+
+/// Lists where the user is signed in, one entry per refresh token family
+#[debug_handler]
+async fn sessions(
+    Extension(auth): Extension<TokenDetails>,
+    State(ctx): State<Arc<AppContext>>,
+) -> Result<Response> {
+    let sessions = ctx.list_sessions(auth.user_pid).await?;
+
+    Ok((StatusCode::OK, Json(json!({ "sessions": sessions }))).into_response())
+}
+
+/// Signs out one of the user's sessions, e.g. a lost device
+#[debug_handler]
+async fn delete_session(
+    Extension(auth): Extension<TokenDetails>,
+    State(ctx): State<Arc<AppContext>>,
+    Path(id): Path<Uuid>,
+) -> Result<Response> {
+    // Someone else's session is reported the same as one that doesn't exist
+    ctx.find_session(id)
+        .await?
+        .filter(|session| session.user_pid == auth.user_pid)
+        .ok_or(crate::Error::Model(ModelError::EntityNotFound))?;
+
+    ctx.revoke_session(id).await?;
+
+    Ok((StatusCode::OK, Json(json!({"message": "Session revoked"}))).into_response())
+}
+
 #[debug_handler]
 async fn logout(
     Extension(auth): Extension<TokenDetails>,
     State(ctx): State<Arc<AppContext>>,
+    cookies: Option<TypedHeader<Cookie>>,
 ) -> Result<Response> {
-    // Remove refresh token from redis and clear cookies
-    ctx.revoke_refresh_token(auth.token_id).await?;
+    // End the session the access token belongs to, or failing that, the presented refresh token
+    if let Some(sid) = auth.family_id {
+        ctx.revoke_session(sid).await?;
+    } else if let Some(refresh_token) = cookies
+        .as_ref()
+        .and_then(|TypedHeader(cookies)| cookies.get("refresh_token"))
+        && let Ok(details) = ctx.auth.refresh.verify_token(refresh_token).await
+        && details.user_pid == auth.user_pid
+    {
+        ctx.revoke_refresh_token(details.token_id).await?;
+    }
+
+    // Stop accepting the access token right away and clear cookies
+    deny_request_token(&ctx, &auth).await?;
+
+    clear_cookies_response(&ctx, json!({"message": "Logout success"}))
+}
+
+/// Signs the user out of every session, including this one
+#[debug_handler]
+async fn logout_all(
+    Extension(auth): Extension<TokenDetails>,
+    State(ctx): State<Arc<AppContext>>,
+) -> Result<Response> {
+    ctx.revoke_user_sessions(auth.user_pid, None).await?;
+    deny_request_token(&ctx, &auth).await?;
+
+    clear_cookies_response(&ctx, json!({"message": "Logged out of all sessions"}))
+}
+
+/// Stops accepting the access token the request was authenticated with before it expires. A
+/// cookie session has no access token; revoking the session already ends it.
+async fn deny_request_token(ctx: &AppContext, auth: &TokenDetails) -> Result<()> {
+    if ctx.config.auth().mode() == AuthMode::Session {
+        return Ok(());
+    }
+
+    ctx.deny_access_token(auth).await
+}
+
+/// Builds a `200 OK` response carrying `body` that clears the cookies of the configured auth mode
+fn clear_cookies_response(ctx: &AppContext, body: serde_json::Value) -> Result<Response> {
+    if ctx.config.auth().mode() == AuthMode::Session {
+        let session_cookie = cookie::Cookie::build((SESSION_COOKIE, ""))
+            .path("/")
+            .secure(true)
+            .http_only(true)
+            .max_age(time::Duration::ZERO)
+            .same_site(cookie::SameSite::Lax);
+
+        let mut res = Response::builder()
+            .status(200)
+            .body(Body::new(body.to_string()))?;
+
+        res.headers_mut().append(
+            SET_COOKIE,
+            HeaderValue::from_str(session_cookie.to_string().as_str())?,
+        );
+
+        return Ok(res);
+    }
 
     let access_cookie = cookie::Cookie::build(("access_token", ""))
         .path("/")
         .http_only(false)
-        .max_age(time::Duration::seconds(ctx.auth.access.exp))
+        .max_age(time::Duration::seconds(ctx.auth.access.exp()))
         .same_site(cookie::SameSite::Lax);
 
     let refresh_cookie = cookie::Cookie::build(("refresh_token", ""))
         .path("/")
         .http_only(true)
-        .max_age(time::Duration::seconds(ctx.auth.refresh.exp))
+        .max_age(time::Duration::seconds(ctx.auth.refresh.exp()))
         .same_site(cookie::SameSite::Lax);
 
     let mut res = Response::builder()
         .status(200)
-        .body(Body::new(json!({"message": "Logout success"}).to_string()))?;
+        .body(Body::new(body.to_string()))?;
 
     res.headers_mut().append(
         SET_COOKIE,
@@ -150,18 +839,31 @@ async fn logout(
 pub fn router(ctx: &Arc<AppContext>) -> Router {
     Router::new()
         .route("/register", post(register))
+        .route("/verify-email", get(verify_email))
+        .route("/resend-verification", post(resend_verification))
+        .route("/forgot-password", post(forgot_password))
+        .route("/reset-password", post(reset_password))
         .route("/login", post(login))
+        .route("/refresh", post(refresh))
+        .route("/confirm-email-change", get(confirm_email_change))
+        .route("/current", get(current).layer(AuthLayer::new(ctx)))
+        .route(
+            "/me",
+            patch(update_profile)
+                .delete(delete_account)
+                .layer(AuthLayer::new(ctx)),
+        )
+        .route("/me/export", get(export_data).layer(AuthLayer::new(ctx)))
         .route(
-            "/current",
-            get(current)
-                .layer(AuthLayer::new(ctx))
-                .layer(RefreshLayer::new(ctx)),
+            "/change-password",
+            post(change_password).layer(AuthLayer::new(ctx)),
         )
+        .route("/sessions", get(sessions).layer(AuthLayer::new(ctx)))
         .route(
-            "/logout",
-            post(logout)
-                .layer(AuthLayer::new(ctx))
-                .layer(RefreshLayer::new(ctx)),
+            "/sessions/{id}",
+            delete(delete_session).layer(AuthLayer::new(ctx)),
         )
+        .route("/logout", post(logout).layer(AuthLayer::new(ctx)))
+        .route("/logout-all", post(logout_all).layer(AuthLayer::new(ctx)))
         .with_state(ctx.clone())
 }
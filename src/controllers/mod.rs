@@ -1 +1,3 @@
 pub mod auth;
+pub mod oauth;
+pub mod well_known;
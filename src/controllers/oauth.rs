@@ -0,0 +1,197 @@
+use std::sync::Arc;
+
+use axum::{
+    Form, Json, Router, debug_handler,
+    extract::State,
+    http::{HeaderValue, StatusCode, header::CACHE_CONTROL},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Basic},
+};
+
+use crate::{
+    Result,
+    config::OAuthClient,
+    context::AppContext,
+    middlewares::AuthError,
+    models::token::{IntrospectionClaims, IntrospectionResponse, TokenRequest, TokenTypeHint},
+};
+
+/// Authenticates the calling client with `client_secret_basic` or `client_secret_post`
+fn authenticate_client<'a>(
+    ctx: &'a AppContext,
+    basic: Option<&Authorization<Basic>>,
+    params: &TokenRequest,
+) -> Result<&'a OAuthClient> {
+    let (client_id, client_secret) = match (basic, &params.client_id, &params.client_secret) {
+        (Some(basic), _, _) => (basic.username(), basic.password()),
+        (None, Some(client_id), Some(client_secret)) => {
+            (client_id.as_str(), client_secret.as_str())
+        }
+        _ => return Err(crate::Error::Auth(AuthError::InvalidClient).into()),
+    };
+
+    ctx.config
+        .oauth()
+        .authenticate(client_id, client_secret)
+        .ok_or_else(|| crate::Error::Auth(AuthError::InvalidClient).into())
+}
+
+async fn introspect_access_token(ctx: &AppContext, token: &str) -> Result<IntrospectionResponse> {
+    let Ok((details, claims)) = ctx
+        .auth
+        .access
+        .verify_token_with_claims::<IntrospectionClaims>(token)
+        .await
+    else {
+        return Ok(IntrospectionResponse::default());
+    };
+
+    if ctx.is_access_token_denied(details.token_id).await? {
+        return Ok(IntrospectionResponse::default());
+    }
+
+    if let Some(sid) = claims.sid
+        && ctx.is_session_revoked(sid).await?
+    {
+        return Ok(IntrospectionResponse::default());
+    }
+
+    Ok(IntrospectionResponse {
+        active: true,
+        sub: Some(details.user_pid.to_string()),
+        exp: details.expires_in,
+        iat: details.issued_at,
+        jti: Some(details.token_id.to_string()),
+        token_type: Some(
+            if claims.cnf.is_some() {
+                "DPoP"
+            } else {
+                "Bearer"
+            }
+            .to_string(),
+        ),
+        scope: claims.scope,
+        cnf: claims.cnf,
+    })
+}
+
+async fn introspect_refresh_token(ctx: &AppContext, token: &str) -> Result<IntrospectionResponse> {
+    let Ok((details, claims)) = ctx
+        .auth
+        .refresh
+        .verify_token_with_claims::<IntrospectionClaims>(token)
+        .await
+    else {
+        return Ok(IntrospectionResponse::default());
+    };
+
+    // A verified refresh token is only live while its record is in the store
+    if !ctx.is_refresh_token_active(details.token_id).await? {
+        return Ok(IntrospectionResponse::default());
+    }
+
+    Ok(IntrospectionResponse {
+        active: true,
+        sub: Some(details.user_pid.to_string()),
+        exp: details.expires_in,
+        iat: details.issued_at,
+        jti: Some(details.token_id.to_string()),
+        token_type: Some("refresh_token".to_string()),
+        scope: claims.scope,
+        cnf: claims.cnf,
+    })
+}
+
+/// RFC 7662 token introspection for services that can't verify tokens themselves.
+///
+/// The hint only decides which token type is tried first; an unknown, expired or revoked token
+/// yields `{"active": false}` without saying why.
+#[debug_handler]
+async fn introspect(
+    State(ctx): State<Arc<AppContext>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Form(params): Form<TokenRequest>,
+) -> Result<Response> {
+    authenticate_client(&ctx, basic.as_deref(), &params)?;
+
+    let response = if params.token_type_hint == Some(TokenTypeHint::RefreshToken) {
+        match introspect_refresh_token(&ctx, &params.token).await? {
+            response if response.active => response,
+            _ => introspect_access_token(&ctx, &params.token).await?,
+        }
+    } else {
+        match introspect_access_token(&ctx, &params.token).await? {
+            response if response.active => response,
+            _ => introspect_refresh_token(&ctx, &params.token).await?,
+        }
+    };
+
+    let mut res = Json(response).into_response();
+
+    res.headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+    Ok(res)
+}
+
+async fn revoke_access_token(ctx: &AppContext, token: &str) -> Result<bool> {
+    let Ok(details) = ctx.auth.access.verify_token(token).await else {
+        return Ok(false);
+    };
+
+    ctx.deny_access_token(&details).await?;
+
+    Ok(true)
+}
+
+async fn revoke_refresh_token(ctx: &AppContext, token: &str) -> Result<bool> {
+    let Ok(details) = ctx.auth.refresh.verify_token(token).await else {
+        return Ok(false);
+    };
+
+    ctx.revoke_refresh_token(details.token_id).await?;
+
+    Ok(true)
+}
+
+/// RFC 7009 token revocation for access and refresh tokens.
+///
+/// Responds `200 OK` whether or not the token was found, so the endpoint can't be used to probe
+/// for valid tokens; only a failed client authentication is reported.
+#[debug_handler]
+async fn revoke(
+    State(ctx): State<Arc<AppContext>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Form(params): Form<TokenRequest>,
+) -> Result<Response> {
+    let client = authenticate_client(&ctx, basic.as_deref(), &params)?;
+
+    // The hint only decides which token type is tried first
+    let revoked = match params.token_type_hint {
+        Some(TokenTypeHint::AccessToken) => {
+            revoke_access_token(&ctx, &params.token).await?
+                || revoke_refresh_token(&ctx, &params.token).await?
+        }
+        _ => {
+            revoke_refresh_token(&ctx, &params.token).await?
+                || revoke_access_token(&ctx, &params.token).await?
+        }
+    };
+
+    if revoked {
+        tracing::info!(client_id = client.client_id(), "Token revoked");
+    }
+
+    Ok(StatusCode::OK.into_response())
+}
+
+pub fn router(ctx: &Arc<AppContext>) -> Router {
+    Router::new()
+        .route("/introspect", post(introspect))
+        .route("/revoke", post(revoke))
+        .with_state(ctx.clone())
+}
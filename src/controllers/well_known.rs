@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use axum::{
+    Json, Router, debug_handler,
+    extract::State,
+    http::{HeaderValue, header::CACHE_CONTROL},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+
+use crate::{Result, context::AppContext};
+
+/// Publishes the access token verification keys as a JSON Web Key Set so other services can
+/// verify access tokens without being handed the PEM.
+#[debug_handler]
+async fn jwks(State(ctx): State<Arc<AppContext>>) -> Result<Response> {
+    let mut res = Json(ctx.auth.access.jwks()).into_response();
+
+    res.headers_mut().insert(
+        CACHE_CONTROL,
+        HeaderValue::from_static("public, max-age=3600"),
+    );
+
+    Ok(res)
+}
+
+pub fn router(ctx: &Arc<AppContext>) -> Router {
+    Router::new()
+        .route("/.well-known/jwks.json", get(jwks))
+        .with_state(ctx.clone())
+}
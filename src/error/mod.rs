@@ -84,6 +84,18 @@ pub enum Error {
     InvalidCredentials,
     #[error("Error occured when signing or verifying token")]
     TokenError,
+    #[error("Invalid key configuration: {0}")]
+    InvalidKeyConfig(String),
+    #[error("Failed to load {0}")]
+    KeyLoad(String),
+    #[error("Invalid mailer configuration: {0}")]
+    InvalidMailerConfig(String),
+    #[error(transparent)]
+    MailAddress(#[from] lettre::address::AddressError),
+    #[error(transparent)]
+    MailMessage(#[from] lettre::error::Error),
+    #[error(transparent)]
+    Smtp(#[from] lettre::transport::smtp::Error),
     #[error(transparent)]
     SerdeJson(#[from] serde_json::error::Error),
     #[error(transparent)]
@@ -1,10 +1,13 @@
 pub mod app;
+pub mod cli;
 pub mod config;
 pub mod context;
 pub mod controllers;
 pub mod error;
+pub mod mailer;
 pub mod middlewares;
 pub mod models;
+pub mod tasks;
 
 pub use self::{
     app::App,
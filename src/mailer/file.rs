@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+
+use futures_util::future::BoxFuture;
+use lettre::message::Mailbox;
+use uuid::Uuid;
+
+use crate::{
+    Result,
+    mailer::{Email, Mailer},
+};
+
+/// Development sink: logs every email and, given a directory, also writes it there as an `.eml`
+/// file that any mail client can open
+pub struct FileMailer {
+    from: Mailbox,
+    dir: Option<PathBuf>,
+}
+
+impl FileMailer {
+    pub fn new(from: Mailbox, dir: Option<PathBuf>) -> Self {
+        Self { from, dir }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let message = email.message(&self.from)?;
+
+            tracing::info!(
+                to = %email.to,
+                subject = %email.subject,
+                "Email not sent, file transport in use:\n{}",
+                email.body
+            );
+
+            if let Some(dir) = &self.dir {
+                tokio::fs::create_dir_all(dir).await?;
+
+                let name = format!(
+                    "{}-{}.eml",
+                    chrono::Utc::now().format("%Y%m%dT%H%M%S"),
+                    Uuid::new_v4()
+                );
+                tokio::fs::write(dir.join(name), message.formatted()).await?;
+            }
+
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,59 @@
+/// Outgoing email. Controllers compose an [`Email`] and hand it to whichever [`Mailer`] the
+/// configuration selects.
+pub mod file;
+pub mod smtp;
+pub mod templates;
+
+use std::sync::Arc;
+
+use futures_util::future::BoxFuture;
+use lettre::{Message, message::Mailbox};
+
+use crate::{
+    Result,
+    config::{MailTransport, MailerConfig},
+};
+
+pub use self::{file::FileMailer, smtp::SmtpMailer};
+
+/// A plain-text email to a single recipient
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Email {
+    pub fn new(to: impl Into<String>, subject: impl Into<String>, body: impl Into<String>) -> Self {
+        Self {
+            to: to.into(),
+            subject: subject.into(),
+            body: body.into(),
+        }
+    }
+
+    fn message(&self, from: &Mailbox) -> Result<Message> {
+        Message::builder()
+            .from(from.clone())
+            .to(self.to.parse::<Mailbox>()?)
+            .subject(&self.subject)
+            .body(self.body.clone())
+            .map_err(Into::into)
+    }
+}
+
+/// Delivers emails; implemented once per transport
+pub trait Mailer: Send + Sync {
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Builds the mailer selected by `config`
+pub fn from_config(config: &MailerConfig) -> Result<Arc<dyn Mailer>> {
+    let from = config.from().parse::<Mailbox>()?;
+
+    Ok(match config.transport() {
+        MailTransport::Smtp { .. } => Arc::new(SmtpMailer::try_new(from, config.transport())?),
+        MailTransport::File { dir } => Arc::new(FileMailer::new(from, dir.clone())),
+    })
+}
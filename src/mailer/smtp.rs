@@ -0,0 +1,62 @@
+use futures_util::future::BoxFuture;
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor, message::Mailbox,
+    transport::smtp::authentication::Credentials,
+};
+
+use crate::{
+    Result,
+    config::{MailTransport, SmtpTls},
+    mailer::{Email, Mailer},
+};
+
+/// Sends through an SMTP relay, reusing pooled connections
+pub struct SmtpMailer {
+    from: Mailbox,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpMailer {
+    pub fn try_new(from: Mailbox, config: &MailTransport) -> Result<Self> {
+        let MailTransport::Smtp {
+            host,
+            port,
+            username,
+            password,
+            tls,
+        } = config
+        else {
+            return Err(crate::Error::InvalidMailerConfig("not an smtp transport".into()).into());
+        };
+
+        let mut builder = match tls {
+            SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        };
+
+        if let Some(port) = port {
+            builder = builder.port(*port);
+        }
+
+        if let (Some(username), Some(password)) = (username, password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Self {
+            from,
+            transport: builder.build(),
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let message = email.message(&self.from)?;
+            self.transport.send(message).await?;
+
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,100 @@
+use crate::mailer::Email;
+
+/// `seconds` in the largest whole unit that fits, e.g. "24 hours"
+fn duration(seconds: i64) -> String {
+    let (amount, unit) = match seconds {
+        s if s >= 86400 && s % 86400 == 0 => (s / 86400, "day"),
+        s if s >= 3600 => (s / 3600, "hour"),
+        s => ((s / 60).max(1), "minute"),
+    };
+
+    format!("{amount} {unit}{}", if amount == 1 { "" } else { "s" })
+}
+
+pub fn verify_email(to: &str, name: &str, link: &str, valid_for: i64) -> Email {
+    Email::new(
+        to,
+        "Verify your email address",
+        format!(
+            "Hi {name},\n\n\
+             Please confirm this is your email address by opening the link below:\n\n\
+             {link}\n\n\
+             The link expires in {}. If you didn't create an account, you can ignore this \
+             email.\n",
+            duration(valid_for),
+        ),
+    )
+}
+
+pub fn reset_password(to: &str, name: &str, link: &str, valid_for: i64) -> Email {
+    Email::new(
+        to,
+        "Reset your password",
+        format!(
+            "Hi {name},\n\n\
+             Someone asked to reset the password of your account. To choose a new one, open the \
+             link below:\n\n\
+             {link}\n\n\
+             The link expires in {} and can only be used once. If you didn't ask for this, you \
+             can ignore this email; your password stays the same.\n",
+            duration(valid_for),
+        ),
+    )
+}
+
+pub fn password_changed(to: &str, name: &str) -> Email {
+    Email::new(
+        to,
+        "Your password was changed",
+        format!(
+            "Hi {name},\n\n\
+             The password of your account was just changed, and devices signed in with the old \
+             password have been signed out.\n\n\
+             If this wasn't you, reset your password right away and get in touch with us.\n"
+        ),
+    )
+}
+
+pub fn confirm_email_change(to: &str, name: &str, link: &str, valid_for: i64) -> Email {
+    Email::new(
+        to,
+        "Confirm your new email address",
+        format!(
+            "Hi {name},\n\n\
+             To start using this address for your account, open the link below:\n\n\
+             {link}\n\n\
+             The link expires in {}. Until then, your account keeps using its current address. \
+             If you didn't ask for this, you can ignore this email.\n",
+            duration(valid_for),
+        ),
+    )
+}
+
+pub fn email_change_requested(to: &str, name: &str, new_email: &str) -> Email {
+    Email::new(
+        to,
+        "Your email address is being changed",
+        format!(
+            "Hi {name},\n\n\
+             Someone signed in to your account asked to change its email address to \
+             {new_email}. The change only happens once the link sent to that address is \
+             opened.\n\n\
+             If this wasn't you, change your password right away; that signs out every other \
+             device.\n"
+        ),
+    )
+}
+
+pub fn account_deleted(to: &str, name: &str, grace_period: i64) -> Email {
+    Email::new(
+        to,
+        "Your account has been deleted",
+        format!(
+            "Hi {name},\n\n\
+             Your account has been deleted and every device signed in to it has been signed \
+             out. Its data is erased for good in {}.\n\n\
+             If this wasn't you, get in touch with us before then.\n",
+            duration(grace_period),
+        ),
+    )
+}
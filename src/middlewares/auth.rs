@@ -9,7 +9,8 @@ use std::{
 use axum::{
     RequestPartsExt,
     body::Body,
-    http::{Request, Response},
+    extract::OriginalUri,
+    http::{HeaderName, Request, Response, header::AUTHORIZATION, request::Parts},
     response::IntoResponse,
 };
 use axum_extra::{
@@ -20,16 +21,44 @@ use axum_extra::{
 use futures_util::future::BoxFuture;
 use tower::{Layer, Service};
 
-use crate::{context::AppContext, middlewares::AuthError};
+use crate::{
+    config::AuthMode,
+    context::AppContext,
+    middlewares::AuthError,
+    models::{
+        session::ClientInfo,
+        token::{AccessClaims, TokenDetails},
+    },
+};
+
+/// Header carrying a DPoP proof (RFC 9449)
+pub const DPOP: HeaderName = HeaderName::from_static("dpop");
+
+/// Cookie naming a server-side session in `AuthMode::Session`
+pub const SESSION_COOKIE: &str = "__Host-session";
 
+/// Admits requests from a signed-in user and hands their identity to handlers as
+/// `Extension<TokenDetails>`.
+///
+/// Whether the identity comes from an access token or a session cookie depends on the
+/// configured `AuthMode`; handlers see the same `TokenDetails` either way.
 #[derive(Clone)]
 pub struct AuthLayer {
     ctx: Arc<AppContext>,
+    mode: AuthMode,
 }
 
 impl AuthLayer {
     pub fn new(ctx: &Arc<AppContext>) -> Self {
-        Self { ctx: ctx.clone() }
+        Self::with_mode(ctx, ctx.config.auth().mode())
+    }
+
+    /// Authenticates with `mode` regardless of the configured one
+    pub fn with_mode(ctx: &Arc<AppContext>, mode: AuthMode) -> Self {
+        Self {
+            ctx: ctx.clone(),
+            mode,
+        }
     }
 }
 
@@ -40,6 +69,7 @@ impl<S> Layer<S> for AuthLayer {
         Self::Service {
             inner,
             ctx: self.ctx.clone(),
+            mode: self.mode,
         }
     }
 }
@@ -48,6 +78,7 @@ impl<S> Layer<S> for AuthLayer {
 pub struct AuthService<S> {
     inner: S,
     ctx: Arc<AppContext>,
+    mode: AuthMode,
 }
 
 impl<S, B> Service<Request<B>> for AuthService<S>
@@ -67,6 +98,7 @@ where
 
     fn call(&mut self, req: Request<B>) -> Self::Future {
         let ctx = self.ctx.clone();
+        let mode = self.mode;
         let clone = self.inner.clone();
 
         // Take the service that is ready
@@ -75,36 +107,20 @@ where
         Box::pin(async move {
             let (mut parts, body) = req.into_parts();
 
-            let access_token = match parts.extract::<TypedHeader<Authorization<Bearer>>>().await {
-                Ok(header) => Some(header.token().to_string()),
-                Err(err) => {
-                    // Access Token not in authorisation header; so check cookies
-                    if matches!(err.reason(), TypedHeaderRejectionReason::Missing) {
-                        parts.extract::<TypedHeader<Cookie>>().await.ok().and_then(
-                            |TypedHeader(cookies)| {
-                                cookies.get("access_token").map(ToString::to_string)
-                            },
-                        )
-                    } else {
-                        // The reason why we wrap the return value in Ok despite it being an error
-                        // is beacause middlewares in Axum cannot return Errors i.e `Error =
-                        // Infallible`
-                        return Ok::<Response<Body>, Self::Error>(
-                            AuthError::InvalidToken.into_response(),
-                        );
-                    }
-                }
+            // The reason why we wrap the error response in Ok is beacause middlewares in Axum
+            // cannot return Errors i.e `Error = Infallible`
+            let token_details = match mode {
+                AuthMode::Token => authenticate_token(&ctx, &mut parts).await,
+                AuthMode::Session => authenticate_session(&ctx, &mut parts).await,
             };
 
-            let Some(access_token) = access_token else {
-                return Ok(AuthError::MissingCredentials.into_response());
+            let token_details = match token_details {
+                Ok(token_details) => token_details,
+                Err(res) => return Ok::<Response<Body>, Self::Error>(res),
             };
 
-            // verify the access token
-            let _token_details = match ctx.auth.access.verify_token(&access_token) {
-                Ok(details) => details,
-                Err(err) => return Ok(err.into_response()),
-            };
+            // Handlers read the authenticated identity through `Extension<TokenDetails>`
+            parts.extensions.insert(token_details);
 
             // Reconstuct the Request
 
@@ -114,3 +130,114 @@ where
         })
     }
 }
+
+/// Resolves the access token from the `Authorization` header or the `access_token` cookie
+async fn authenticate_token(
+    ctx: &AppContext,
+    parts: &mut Parts,
+) -> Result<TokenDetails, Response<Body>> {
+    // `Authorization: DPoP <token>` presents a sender-constrained token
+    let dpop_token = parts
+        .headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("DPoP "))
+        .map(ToString::to_string);
+
+    let access_token = match dpop_token.clone() {
+        Some(token) => Some(token),
+        None => match parts.extract::<TypedHeader<Authorization<Bearer>>>().await {
+            Ok(header) => Some(header.token().to_string()),
+            Err(err) => {
+                // Access Token not in authorisation header; so check cookies
+                if matches!(err.reason(), TypedHeaderRejectionReason::Missing) {
+                    parts.extract::<TypedHeader<Cookie>>().await.ok().and_then(
+                        |TypedHeader(cookies)| cookies.get("access_token").map(ToString::to_string),
+                    )
+                } else {
+                    return Err(AuthError::InvalidToken.into_response());
+                }
+            }
+        },
+    };
+
+    let Some(access_token) = access_token else {
+        return Err(AuthError::MissingCredentials.into_response());
+    };
+
+    // verify the access token
+    let (mut token_details, claims) = match ctx
+        .auth
+        .access
+        .verify_token_with_claims::<AccessClaims>(&access_token)
+        .await
+    {
+        Ok(verified) => verified,
+        Err(err) => return Err(err.into_response()),
+    };
+
+    // A bound token is only accepted with the DPoP scheme and a fresh proof from its key
+    if let Some(cnf) = claims.dpop.cnf {
+        let proof = parts
+            .headers
+            .get(DPOP)
+            .and_then(|value| value.to_str().ok());
+
+        let Some(proof) = proof.filter(|_| dpop_token.is_some()) else {
+            return Err(AuthError::InvalidDpopProof.into_response());
+        };
+
+        let path = parts
+            .extensions
+            .get::<OriginalUri>()
+            .map_or_else(|| parts.uri.path(), |uri| uri.path());
+
+        match ctx
+            .verify_dpop_proof(proof, parts.method.as_str(), path, Some(&access_token))
+            .await
+        {
+            Ok(proof) if proof.jkt == cnf.jkt => (),
+            Ok(_) => return Err(AuthError::InvalidDpopProof.into_response()),
+            Err(err) => return Err(err.into_response()),
+        }
+    }
+
+    // A verified token may still have been revoked before its expiry
+    match ctx.is_access_token_denied(token_details.token_id).await {
+        Ok(false) => (),
+        Ok(true) => return Err(AuthError::TokenRevoked.into_response()),
+        Err(err) => return Err(err.into_response()),
+    }
+
+    // ... or its whole session may have been
+    if let Some(sid) = claims.sid {
+        match ctx.is_session_revoked(sid).await {
+            Ok(false) => token_details.family_id = Some(sid),
+            Ok(true) => return Err(AuthError::TokenRevoked.into_response()),
+            Err(err) => return Err(err.into_response()),
+        }
+    }
+
+    Ok(token_details)
+}
+
+/// Resolves the `__Host-session` cookie to the session it names
+async fn authenticate_session(
+    ctx: &AppContext,
+    parts: &mut Parts,
+) -> Result<TokenDetails, Response<Body>> {
+    let Some(token) = parts
+        .extract::<TypedHeader<Cookie>>()
+        .await
+        .ok()
+        .and_then(|TypedHeader(cookies)| cookies.get(SESSION_COOKIE).map(ToString::to_string))
+    else {
+        return Err(AuthError::MissingCredentials.into_response());
+    };
+
+    let Ok(client) = parts.extract::<ClientInfo>().await;
+
+    ctx.resolve_session_cookie(&token, &client)
+        .await
+        .map_err(IntoResponse::into_response)
+}
@@ -9,6 +9,24 @@ use serde_json::json;
 pub enum AuthError {
     #[error("Invalid token")]
     InvalidToken,
+    #[error("Refresh token has already been used")]
+    RefreshTokenReused,
+    #[error("Token has been revoked")]
+    TokenRevoked,
+    #[error("Session has expired")]
+    SessionExpired,
+    #[error("Too many active sessions")]
+    SessionLimitReached,
+    #[error("Invalid client credentials")]
+    InvalidClient,
+    #[error("Invalid DPoP proof")]
+    InvalidDpopProof,
+    #[error("Email address not verified")]
+    EmailNotVerified,
+    #[error("Password or a recent sign-in required")]
+    ReauthenticationRequired,
+    #[error("Invalid or expired token")]
+    InvalidOneTimeToken,
     #[error("Credentials missing from request")]
     MissingCredentials,
     #[error("Token creation failed")]
@@ -29,6 +47,21 @@ impl AuthError {
     pub fn response(&self) -> Response {
         let (status, message) = match self {
             Self::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid token"),
+            Self::RefreshTokenReused => (
+                StatusCode::UNAUTHORIZED,
+                "Refresh token has already been used",
+            ),
+            Self::TokenRevoked => (StatusCode::UNAUTHORIZED, "Token has been revoked"),
+            Self::SessionExpired => (StatusCode::UNAUTHORIZED, "Session has expired"),
+            Self::SessionLimitReached => (StatusCode::FORBIDDEN, "Too many active sessions"),
+            Self::InvalidClient => (StatusCode::UNAUTHORIZED, "Invalid client credentials"),
+            Self::InvalidDpopProof => (StatusCode::UNAUTHORIZED, "Invalid DPoP proof"),
+            Self::EmailNotVerified => (StatusCode::FORBIDDEN, "Email address not verified"),
+            Self::ReauthenticationRequired => (
+                StatusCode::FORBIDDEN,
+                "Password or a recent sign-in required",
+            ),
+            Self::InvalidOneTimeToken => (StatusCode::BAD_REQUEST, "Invalid or expired token"),
             Self::MissingCredentials => {
                 (StatusCode::BAD_REQUEST, "Credentials missing from request")
             }
@@ -1,6 +1,5 @@
 pub mod auth;
 pub mod error;
-pub mod refresh;
 pub mod trace;
 
-pub use self::{auth::AuthLayer, error::AuthError, refresh::RefreshLayer, trace::*};
+pub use self::{auth::AuthLayer, error::AuthError, trace::*};
@@ -0,0 +1,318 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{Result, middlewares::AuthError, models::token::jwk_thumbprint};
+
+/// Claims of a DPoP proof JWT (RFC 9449 section 4.2)
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DpopProofClaims {
+    pub jti: String,
+    pub htm: String,
+    pub htu: String,
+    pub iat: i64,
+    /// Hash of the access token, required when the proof accompanies one
+    pub ath: Option<String>,
+}
+
+/// `cnf` claim binding a token to the key that signed the DPoP proof
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Confirmation {
+    pub jkt: String,
+}
+
+/// Extra claims carried by sender-constrained tokens; unbound tokens serialise to nothing
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct DpopClaims {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+}
+
+impl DpopClaims {
+    pub fn bound_to(jkt: Option<&str>) -> Self {
+        Self {
+            cnf: jkt.map(|jkt| Confirmation {
+                jkt: jkt.to_string(),
+            }),
+        }
+    }
+}
+
+/// A DPoP proof whose signature, method, URL, age and access token hash have been checked
+#[derive(Debug, Clone)]
+pub struct DpopProof {
+    /// RFC 7638 thumbprint of the key that signed the proof
+    pub jkt: String,
+    pub jti: String,
+}
+
+impl DpopProof {
+    /// Validates a proof for a request to `method` `htu`.
+    ///
+    /// Replay is not checked here since it needs the shared cache; see
+    /// `AppContext::verify_dpop_proof`.
+    pub fn verify(
+        proof: &str,
+        method: &str,
+        htu: &str,
+        access_token: Option<&str>,
+        max_age: i64,
+    ) -> Result<Self> {
+        let invalid = || crate::Error::Auth(AuthError::InvalidDpopProof);
+
+        let header = jsonwebtoken::decode_header(proof).map_err(|_| invalid())?;
+
+        if header.typ.as_deref() != Some("dpop+jwt") {
+            return Err(invalid().into());
+        }
+
+        // The key travels in the proof, so a shared secret would make it worthless
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(invalid().into());
+        }
+
+        let jwk = header.jwk.ok_or_else(invalid)?;
+        let decoding_key = DecodingKey::from_jwk(&jwk).map_err(|_| invalid())?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.validate_exp = false;
+        validation.validate_aud = false;
+        validation.set_required_spec_claims::<&str>(&[]);
+
+        let claims = jsonwebtoken::decode::<DpopProofClaims>(proof, &decoding_key, &validation)
+            .map_err(|_| invalid())?
+            .claims;
+
+        if !claims.htm.eq_ignore_ascii_case(method) || strip_query(&claims.htu) != htu {
+            return Err(invalid().into());
+        }
+
+        if (chrono::Utc::now().timestamp() - claims.iat).abs() > max_age {
+            return Err(invalid().into());
+        }
+
+        if let Some(access_token) = access_token {
+            let ath = URL_SAFE_NO_PAD.encode(Sha256::digest(access_token.as_bytes()));
+
+            if claims.ath.as_deref() != Some(ath.as_str()) {
+                return Err(invalid().into());
+            }
+        }
+
+        Ok(Self {
+            jkt: jwk_thumbprint(&jwk),
+            jti: claims.jti,
+        })
+    }
+}
+
+/// `htu` is compared without its query and fragment
+fn strip_query(htu: &str) -> &str {
+    htu.split(['?', '#']).next().unwrap_or(htu)
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::password_hash::rand_core::OsRng;
+    use jsonwebtoken::{
+        EncodingKey, Header,
+        jwk::{AlgorithmParameters, CommonParameters, Jwk, OctetKeyParameters, OctetKeyType},
+    };
+    use rsa::pkcs8::EncodePrivateKey;
+
+    use super::*;
+
+    const HTU: &str = "https://auth.example.com/auth/refresh";
+    const MAX_AGE: i64 = 300;
+
+    struct Signer {
+        key: EncodingKey,
+        jwk: Jwk,
+    }
+
+    impl Signer {
+        fn new() -> Self {
+            let secret = p256::SecretKey::random(&mut OsRng);
+            let der = secret.to_pkcs8_der().unwrap();
+            let key = EncodingKey::from_ec_der(der.as_bytes());
+            let jwk = Jwk::from_encoding_key(&key, Algorithm::ES256).unwrap();
+
+            Self { key, jwk }
+        }
+
+        fn header(&self) -> Header {
+            let mut header = Header::new(Algorithm::ES256);
+            header.typ = Some("dpop+jwt".to_string());
+            header.jwk = Some(self.jwk.clone());
+            header
+        }
+
+        fn sign(&self, header: &Header, claims: &DpopProofClaims) -> String {
+            jsonwebtoken::encode(header, claims, &self.key).unwrap()
+        }
+
+        fn proof(&self, claims: &DpopProofClaims) -> String {
+            self.sign(&self.header(), claims)
+        }
+    }
+
+    fn claims() -> DpopProofClaims {
+        DpopProofClaims {
+            jti: "proof-1".to_string(),
+            htm: "POST".to_string(),
+            htu: HTU.to_string(),
+            iat: chrono::Utc::now().timestamp(),
+            ath: None,
+        }
+    }
+
+    fn ath(access_token: &str) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(access_token.as_bytes()))
+    }
+
+    fn verify(proof: &str, access_token: Option<&str>) -> Result<DpopProof> {
+        DpopProof::verify(proof, "POST", HTU, access_token, MAX_AGE)
+    }
+
+    fn assert_invalid(result: Result<DpopProof>) {
+        let err = result.expect_err("proof should be rejected");
+
+        assert!(matches!(
+            err.0.downcast_ref::<crate::Error>(),
+            Some(crate::Error::Auth(AuthError::InvalidDpopProof))
+        ));
+    }
+
+    #[test]
+    fn accepts_a_valid_proof() {
+        let signer = Signer::new();
+
+        let proof = verify(&signer.proof(&claims()), None).unwrap();
+
+        assert_eq!(proof.jkt, jwk_thumbprint(&signer.jwk));
+        assert_eq!(proof.jti, "proof-1");
+    }
+
+    #[test]
+    fn ignores_the_method_case_and_the_query_of_htu() {
+        let signer = Signer::new();
+        let claims = DpopProofClaims {
+            htm: "post".to_string(),
+            htu: format!("{}?source=app#top", HTU),
+            ..claims()
+        };
+
+        assert!(verify(&signer.proof(&claims), None).is_ok());
+    }
+
+    #[test]
+    fn rejects_another_method() {
+        let signer = Signer::new();
+        let claims = DpopProofClaims {
+            htm: "GET".to_string(),
+            ..claims()
+        };
+
+        assert_invalid(verify(&signer.proof(&claims), None));
+    }
+
+    #[test]
+    fn rejects_another_url() {
+        let signer = Signer::new();
+        let claims = DpopProofClaims {
+            htu: "https://auth.example.com/auth/login".to_string(),
+            ..claims()
+        };
+
+        assert_invalid(verify(&signer.proof(&claims), None));
+    }
+
+    #[test]
+    fn rejects_a_stale_or_future_iat() {
+        let signer = Signer::new();
+        let now = chrono::Utc::now().timestamp();
+
+        for iat in [now - MAX_AGE - 10, now + MAX_AGE + 10] {
+            let claims = DpopProofClaims { iat, ..claims() };
+
+            assert_invalid(verify(&signer.proof(&claims), None));
+        }
+    }
+
+    #[test]
+    fn checks_the_access_token_hash() {
+        let signer = Signer::new();
+        let claims = DpopProofClaims {
+            ath: Some(ath("access-token")),
+            ..claims()
+        };
+        let proof = signer.proof(&claims);
+
+        assert!(verify(&proof, Some("access-token")).is_ok());
+        assert_invalid(verify(&proof, Some("another-access-token")));
+    }
+
+    #[test]
+    fn rejects_a_missing_access_token_hash() {
+        let signer = Signer::new();
+
+        assert_invalid(verify(&signer.proof(&claims()), Some("access-token")));
+    }
+
+    #[test]
+    fn rejects_another_typ() {
+        let signer = Signer::new();
+
+        for typ in [Some("JWT"), None] {
+            let mut header = signer.header();
+            header.typ = typ.map(ToString::to_string);
+
+            assert_invalid(verify(&signer.sign(&header, &claims()), None));
+        }
+    }
+
+    #[test]
+    fn rejects_hmac_algorithms() {
+        let secret = b"a-secret-anyone-holding-the-proof-could-read";
+        let jwk = Jwk {
+            common: CommonParameters::default(),
+            algorithm: AlgorithmParameters::OctetKey(OctetKeyParameters {
+                key_type: OctetKeyType::Octet,
+                value: URL_SAFE_NO_PAD.encode(secret),
+            }),
+        };
+
+        for alg in [Algorithm::HS256, Algorithm::HS384, Algorithm::HS512] {
+            let mut header = Header::new(alg);
+            header.typ = Some("dpop+jwt".to_string());
+            header.jwk = Some(jwk.clone());
+
+            let proof = jsonwebtoken::encode(&header, &claims(), &EncodingKey::from_secret(secret))
+                .unwrap();
+
+            assert_invalid(verify(&proof, None));
+        }
+    }
+
+    #[test]
+    fn rejects_a_proof_without_its_key() {
+        let signer = Signer::new();
+        let mut header = signer.header();
+        header.jwk = None;
+
+        assert_invalid(verify(&signer.sign(&header, &claims()), None));
+    }
+
+    #[test]
+    fn rejects_a_signature_from_another_key() {
+        let signer = Signer::new();
+        let mut header = signer.header();
+        header.jwk = Some(Signer::new().jwk);
+
+        assert_invalid(verify(&signer.sign(&header, &claims()), None));
+    }
+}
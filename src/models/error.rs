@@ -5,12 +5,16 @@ use axum::{
 };
 use serde_json::json;
 
+use crate::models::validation::ValidationErrors;
+
 #[derive(Debug, thiserror::Error)]
 pub enum ModelError {
     #[error("Model Already exists")]
     EntityAlreadyExists,
     #[error("Model not found")]
     EntityNotFound,
+    #[error("Validation failed: {0}")]
+    Validation(ValidationErrors),
     #[error(transparent)]
     Sqlx(#[from] sqlx::Error),
     #[error(transparent)]
@@ -22,6 +26,14 @@ pub type ModelResult<T, E = ModelError> = Result<T, E>;
 impl ModelError {
     pub fn response(&self) -> Response {
         let (status, message) = match self {
+            Self::Validation(errors) => {
+                let body = Json(json!({
+                    "error": "Validation failed",
+                    "fields": errors
+                }));
+
+                return (StatusCode::UNPROCESSABLE_ENTITY, body).into_response();
+            }
             Self::EntityAlreadyExists => (StatusCode::CONFLICT, "Entity already exists"),
             Self::EntityNotFound => (StatusCode::NOT_FOUND, "Entity not found"),
             _ => (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error"),
@@ -0,0 +1,45 @@
+use std::future::Future;
+
+use serde_json::{Map, Value, json};
+use uuid::Uuid;
+
+use crate::{
+    Result,
+    context::AppContext,
+    models::{User, session::Session},
+};
+
+/// Personal data a model holds about a user, included in their `GET /auth/me/export` archive.
+///
+/// Each model with per-user data implements this and is added to [`export_user_data`], which
+/// puts its export under `SECTION`.
+pub trait PersonalData {
+    const SECTION: &'static str;
+
+    fn export(ctx: &AppContext, user_pid: Uuid) -> impl Future<Output = Result<Value>> + Send;
+}
+
+/// Assembles everything the service holds about a user into one JSON archive
+pub async fn export_user_data(ctx: &AppContext, user_pid: Uuid) -> Result<Value> {
+    let mut archive = Map::new();
+
+    archive.insert(
+        "exported_at".to_string(),
+        json!(chrono::Utc::now().to_rfc3339()),
+    );
+
+    add_section::<User>(&mut archive, ctx, user_pid).await?;
+    add_section::<Session>(&mut archive, ctx, user_pid).await?;
+
+    Ok(Value::Object(archive))
+}
+
+async fn add_section<T: PersonalData>(
+    archive: &mut Map<String, Value>,
+    ctx: &AppContext,
+    user_pid: Uuid,
+) -> Result<()> {
+    archive.insert(T::SECTION.to_string(), T::export(ctx, user_pid).await?);
+
+    Ok(())
+}
@@ -1,6 +1,10 @@
+pub mod dpop;
 pub mod error;
+pub mod export;
+pub mod session;
 pub mod token;
 pub mod users;
+pub mod validation;
 
 pub use self::{
     error::{ModelError, ModelResult},
@@ -0,0 +1,115 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{context::AppContext, models::export::PersonalData};
+
+/// A signed-in device or browser, i.e. one refresh token family.
+///
+/// Stored in Redis under `session:{id}` and listed through the `user_sessions:{pid}` index.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Session {
+    /// Same as the `family_id` of the session's refresh tokens
+    pub id: Uuid,
+    pub user_pid: Uuid,
+    pub created_at: DateTime<Utc>,
+    /// Last time a refresh token of the session was rotated
+    pub last_used_at: DateTime<Utc>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl Session {
+    pub fn new(id: Uuid, user_pid: Uuid, client: &ClientInfo) -> Self {
+        let now = Utc::now();
+
+        Self {
+            id,
+            user_pid,
+            created_at: now,
+            last_used_at: now,
+            ip: client.ip.clone(),
+            user_agent: client.user_agent.clone(),
+        }
+    }
+
+    /// Records a use of the session from `client`
+    pub fn touch(&mut self, client: &ClientInfo) {
+        self.last_used_at = Utc::now();
+
+        if client.ip.is_some() {
+            self.ip.clone_from(&client.ip);
+        }
+
+        if client.user_agent.is_some() {
+            self.user_agent.clone_from(&client.user_agent);
+        }
+    }
+}
+
+/// Active sessions, each with the record of its live refresh token. The token itself is left
+/// out, as it is a credential rather than personal data.
+impl PersonalData for Session {
+    const SECTION: &'static str = "sessions";
+
+    async fn export(ctx: &AppContext, user_pid: Uuid) -> crate::Result<serde_json::Value> {
+        let mut sessions = Vec::new();
+
+        for session in ctx.list_sessions(user_pid).await? {
+            let refresh_token = ctx
+                .find_family_refresh_token(session.id)
+                .await?
+                .map(|details| {
+                    json!({
+                        "token_id": details.token_id,
+                        "issued_at": details.issued_at,
+                        "expires_at": details.expires_in,
+                        "dpop_bound": details.jkt.is_some(),
+                    })
+                });
+
+            sessions.push(json!({
+                "session": session,
+                "refresh_token": refresh_token,
+            }));
+        }
+
+        Ok(json!(sessions))
+    }
+}
+
+/// Where a request came from, as far as the session list is concerned.
+///
+/// The IP is the peer address of the connection, so it is only available when the server is
+/// started with connect info.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string);
+
+        Ok(Self { ip, user_agent })
+    }
+}
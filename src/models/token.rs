@@ -1,15 +1,41 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
+use crate::models::dpop::{Confirmation, DpopClaims};
+
 /// The token string deserialises to this struct
 /// The `sub` field will be the user's pid
+///
+/// Embedding apps can attach their own typed claims (roles, tenant, ...) through `C`; they are
+/// flattened next to the registered claims.
 #[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct TokenClaims {
+pub struct TokenClaims<C = NoClaims> {
+    pub iss: String,
+    pub aud: Vec<String>,
     pub sub: String,
     pub id: String,
     pub exp: i64,
     pub iat: i64,
     pub nbf: i64,
+    #[serde(flatten)]
+    pub extra: C,
+}
+
+/// Default for tokens that carry no application-specific claims
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct NoClaims {}
+
+/// Extra claims of access tokens
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct AccessClaims {
+    /// Session the token was issued for, so revoking the session also stops its access tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
+    #[serde(flatten)]
+    pub dpop: DpopClaims,
 }
 
 /// This struct will let us store our token in Redis
@@ -19,4 +45,145 @@ pub struct TokenDetails {
     pub token_id: Uuid,
     pub user_pid: Uuid,
     pub expires_in: Option<i64>,
+    #[serde(default)]
+    pub issued_at: Option<i64>,
+    /// Refresh tokens minted from the same login share a family so that reuse of a
+    /// rotated token can revoke every descendant. The family is the session; for an access
+    /// token this is its `sid`
+    #[serde(default)]
+    pub family_id: Option<Uuid>,
+    /// Thumbprint of the DPoP key the token is bound to, if any
+    #[serde(default)]
+    pub jkt: Option<String>,
+}
+
+/// What an opaque reference token resolves to in Redis
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OpaqueTokenRecord {
+    pub details: TokenDetails,
+    /// The application's own claims, kept as they would have been embedded in a JWT
+    pub claims: serde_json::Value,
+}
+
+/// Body accepted by the refresh endpoint for clients that can't rely on cookies
+#[derive(Debug, Deserialize, Clone)]
+pub struct RefreshParams {
+    pub refresh_token: String,
+}
+
+/// Form accepted by the OAuth introspection and revocation endpoints.
+///
+/// Clients using `client_secret_post` send their credentials in the form instead of an
+/// `Authorization: Basic` header.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TokenRequest {
+    pub token: String,
+    pub token_type_hint: Option<TokenTypeHint>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenTypeHint {
+    AccessToken,
+    RefreshToken,
+    /// Hints are advisory, so unknown values are accepted and searched like no hint
+    #[serde(other)]
+    Unknown,
+}
+
+/// RFC 7662 introspection response; everything but `active` is omitted for inactive tokens
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Lets resource servers enforce DPoP binding (RFC 9449 section 6.2)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+}
+
+/// Claims introspection reports on: `scope` when the embedding app issues one, and `cnf` for
+/// DPoP-bound tokens. `sid` is only read, to reject access tokens of revoked sessions
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct IntrospectionClaims {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
+    pub scope: Option<String>,
+    pub cnf: Option<Confirmation>,
+}
+
+/// RFC 7638 SHA-256 thumbprint of a JWK, base64url encoded.
+///
+/// Only the required members are hashed, in lexicographic order, so the thumbprint is stable
+/// however the key was serialised.
+pub fn jwk_thumbprint(jwk: &Jwk) -> String {
+    let canonical = match &jwk.algorithm {
+        AlgorithmParameters::RSA(rsa) => {
+            format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, rsa.e, rsa.n)
+        }
+        AlgorithmParameters::EllipticCurve(ec) => format!(
+            r#"{{"crv":{},"kty":"EC","x":"{}","y":"{}"}}"#,
+            serde_json::to_string(&ec.curve).unwrap_or_default(),
+            ec.x,
+            ec.y
+        ),
+        AlgorithmParameters::OctetKeyPair(okp) => format!(
+            r#"{{"crv":{},"kty":"OKP","x":"{}"}}"#,
+            serde_json::to_string(&okp.curve).unwrap_or_default(),
+            okp.x
+        ),
+        AlgorithmParameters::OctetKey(oct) => {
+            format!(r#"{{"k":"{}","kty":"oct"}}"#, oct.value)
+        }
+    };
+
+    URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::jwk::{CommonParameters, KeyAlgorithm, RSAKeyParameters, RSAKeyType};
+
+    use super::*;
+
+    /// The example key of RFC 7638 section 3.1
+    fn rfc7638_key() -> Jwk {
+        Jwk {
+            common: CommonParameters::default(),
+            algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw".to_string(),
+                e: "AQAB".to_string(),
+            }),
+        }
+    }
+
+    #[test]
+    fn thumbprint_matches_rfc7638_example() {
+        assert_eq!(
+            jwk_thumbprint(&rfc7638_key()),
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+    }
+
+    #[test]
+    fn thumbprint_ignores_optional_members() {
+        let mut jwk = rfc7638_key();
+        jwk.common.key_id = Some("2011-04-29".to_string());
+        jwk.common.key_algorithm = Some(KeyAlgorithm::RS256);
+
+        assert_eq!(jwk_thumbprint(&jwk), jwk_thumbprint(&rfc7638_key()));
+    }
 }
@@ -9,25 +9,79 @@ use chrono::{
     format::{DelayedFormat, StrftimeItems},
 };
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sqlx::{Encode, Executor, Postgres, prelude::FromRow};
 use uuid::Uuid;
 
-use crate::{Result, models::ModelError};
+use crate::{
+    Result,
+    config::PasswordPolicy,
+    context::AppContext,
+    models::{
+        ModelError,
+        export::PersonalData,
+        validation::{ValidationErrors, validate_email, validate_name},
+    },
+};
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// Body of `POST /auth/register`; missing fields are left empty for `validate` to report
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct RegisterUser<'a> {
     email: Cow<'a, str>,
     name: Cow<'a, str>,
     password: Cow<'a, str>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// Body of `POST /auth/login`; missing fields are left empty for `validate` to report
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct LoginUser<'a> {
     email: Cow<'a, str>,
     password: Cow<'a, str>,
 }
 
+impl RegisterUser<'_> {
+    pub fn validate(&self, policy: &PasswordPolicy) -> Result<()> {
+        let mut errors = ValidationErrors::default();
+
+        validate_email("email", &self.email, &mut errors);
+        validate_name("name", &self.name, &mut errors);
+
+        if self.password.is_empty() {
+            errors.add("password", "is required");
+        } else {
+            for problem in policy.check(&self.password) {
+                errors.add("password", problem);
+            }
+        }
+
+        errors.into_result()
+    }
+}
+
 impl LoginUser<'_> {
+    /// Only checks what a login needs to be attempted; the password policy may have changed
+    /// since the account was created, except that nothing above its maximum length gets hashed
+    pub fn validate(&self, policy: &PasswordPolicy) -> Result<()> {
+        let mut errors = ValidationErrors::default();
+
+        if self.email.trim().is_empty() {
+            errors.add("email", "is required");
+        }
+
+        if self.password.is_empty() {
+            errors.add("password", "is required");
+        } else if self.password.chars().count() > policy.max_length() {
+            errors.add(
+                "password",
+                format!("must be at most {} characters", policy.max_length()),
+            );
+        }
+
+        errors.into_result()
+    }
+
     pub fn email(&self) -> &str {
         &self.email
     }
@@ -37,6 +91,109 @@ impl LoginUser<'_> {
     }
 }
 
+/// Body of `POST /auth/resend-verification`
+#[derive(Debug, Deserialize, Clone)]
+pub struct EmailParams {
+    pub email: String,
+}
+
+/// Body of `POST /auth/reset-password`
+#[derive(Debug, Deserialize, Clone)]
+pub struct ResetPasswordParams {
+    pub token: String,
+    pub password: String,
+}
+
+impl ResetPasswordParams {
+    pub fn validate(&self, policy: &PasswordPolicy) -> Result<()> {
+        let mut errors = ValidationErrors::default();
+
+        for problem in policy.check(&self.password) {
+            errors.add("password", problem);
+        }
+
+        errors.into_result()
+    }
+}
+
+/// Body of `POST /auth/change-password`
+#[derive(Debug, Deserialize, Clone)]
+pub struct ChangePasswordParams {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+impl ChangePasswordParams {
+    pub fn validate(&self, policy: &PasswordPolicy) -> Result<()> {
+        let mut errors = ValidationErrors::default();
+
+        if self.current_password.is_empty() {
+            errors.add("current_password", "is required");
+        }
+
+        for problem in policy.check(&self.new_password) {
+            errors.add("new_password", problem);
+        }
+
+        if self.new_password == self.current_password {
+            errors.add("new_password", "must differ from the current password");
+        }
+
+        errors.into_result()
+    }
+}
+
+/// Body of `PATCH /auth/me`; absent fields are left as they are
+#[derive(Debug, Deserialize, Clone)]
+pub struct UpdateProfileParams {
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+impl UpdateProfileParams {
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = ValidationErrors::default();
+
+        if let Some(name) = &self.name {
+            validate_name("name", name, &mut errors);
+        }
+
+        if let Some(email) = &self.email {
+            validate_email("email", email, &mut errors);
+        }
+
+        errors.into_result()
+    }
+}
+
+/// Body of `DELETE /auth/me`; the password can be left out shortly after signing in
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct DeleteAccountParams {
+    pub password: Option<String>,
+}
+
+/// What the link confirming a new email address resolves to
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EmailChange {
+    pub user_pid: Uuid,
+    pub old_email: String,
+    pub new_email: String,
+}
+
+/// Query of the link in a verification email
+#[derive(Debug, Deserialize, Clone)]
+pub struct TokenParams {
+    pub token: String,
+}
+
+/// What an emailed link resolves to: the address it was sent to, for the user it was sent for.
+/// A link only confirms that address, so one sent before the address changed is useless.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EmailConfirmation {
+    pub user_pid: Uuid,
+    pub email: String,
+}
+
 #[derive(Debug, Deserialize, Clone, FromRow, Encode)]
 pub struct User {
     id: i32,
@@ -46,6 +203,9 @@ pub struct User {
     password: String,
     created_at: DateTime<FixedOffset>,
     updated_at: DateTime<FixedOffset>,
+    email_verified_at: Option<DateTime<FixedOffset>>,
+    /// Set when the user deletes their account; the row is purged after a grace period
+    deleted_at: Option<DateTime<FixedOffset>>,
 }
 
 impl User {
@@ -64,7 +224,13 @@ impl User {
         .bind(new_user.name.trim())
         .bind(password_hash(&new_user.password)?)
         .fetch_one(db)
-        .await?;
+        .await
+        .map_err(|err| match err {
+            sqlx::Error::Database(err) if err.is_unique_violation() => {
+                crate::Error::Model(ModelError::EntityAlreadyExists)
+            }
+            err => crate::Error::Model(err.into()),
+        })?;
         Ok(user)
     }
 
@@ -74,7 +240,7 @@ impl User {
     {
         sqlx::query_as(
             r"
-            SELECT * FROM users WHERE email = $1
+            SELECT * FROM users WHERE email = $1 AND deleted_at IS NULL
         ",
         )
         .bind(email.trim())
@@ -89,7 +255,7 @@ impl User {
     {
         sqlx::query_as(
             r"
-            SELECT * FROM users WHERE pid = $1
+            SELECT * FROM users WHERE pid = $1 AND deleted_at IS NULL
         ",
         )
         .bind(pid)
@@ -98,6 +264,122 @@ impl User {
         .ok_or(crate::Error::Model(ModelError::EntityNotFound).into())
     }
 
+    pub async fn mark_email_verified<'e, C>(&self, db: &C) -> Result<Self>
+    where
+        for<'a> &'a C: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as(
+            r"
+            UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW())
+            WHERE pid = $1
+            RETURNING *
+        ",
+        )
+        .bind(self.pid)
+        .fetch_one(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn update_name<'e, C>(&self, db: &C, name: &str) -> Result<Self>
+    where
+        for<'a> &'a C: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as(
+            r"
+            UPDATE users SET name = $2
+            WHERE pid = $1
+            RETURNING *
+        ",
+        )
+        .bind(self.pid)
+        .bind(name.trim())
+        .fetch_one(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Switches to an address the user has just proven they own, so it is verified as well
+    pub async fn update_email<'e, C>(&self, db: &C, email: &str) -> Result<Self>
+    where
+        for<'a> &'a C: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as(
+            r"
+            UPDATE users SET email = $2, email_verified_at = NOW()
+            WHERE pid = $1
+            RETURNING *
+        ",
+        )
+        .bind(self.pid)
+        .bind(email.trim())
+        .fetch_one(db)
+        .await
+        .map_err(|err| match err {
+            sqlx::Error::Database(err) if err.is_unique_violation() => {
+                crate::Error::Model(ModelError::EntityAlreadyExists).into()
+            }
+            err => crate::Error::Model(err.into()).into(),
+        })
+    }
+
+    /// Marks the account deleted. It can no longer be found or signed in to, and is purged for
+    /// good by [`User::purge_deleted`] once the grace period is over.
+    pub async fn soft_delete<'e, C>(&self, db: &C) -> Result<()>
+    where
+        for<'a> &'a C: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query(
+            r"
+            UPDATE users SET deleted_at = NOW()
+            WHERE pid = $1 AND deleted_at IS NULL
+        ",
+        )
+        .bind(self.pid)
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Permanently removes accounts deleted more than `grace_period` seconds ago and returns how
+    /// many there were
+    pub async fn purge_deleted<'e, C>(db: &C, grace_period: i64) -> Result<u64>
+    where
+        for<'a> &'a C: Executor<'e, Database = Postgres>,
+    {
+        let result = sqlx::query(
+            r"
+            DELETE FROM users
+            WHERE deleted_at < NOW() - make_interval(secs => $1)
+        ",
+        )
+        .bind(grace_period as f64)
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Replaces the password hash; callers are expected to have checked the password policy
+    pub async fn update_password<'e, C>(&self, db: &C, new_password: &str) -> Result<Self>
+    where
+        for<'a> &'a C: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as(
+            r"
+            UPDATE users SET password = $2
+            WHERE pid = $1
+            RETURNING *
+        ",
+        )
+        .bind(self.pid)
+        .bind(password_hash(new_password)?)
+        .fetch_one(db)
+        .await
+        .map_err(Into::into)
+    }
+
     pub fn verify_password(&self, password: &str) -> Result<()> {
         let password_hash =
             PasswordHash::new(&self.password).map_err(crate::Error::PasswordHash)?;
@@ -128,11 +410,33 @@ impl User {
         self.id
     }
 
+    pub fn is_email_verified(&self) -> bool {
+        self.email_verified_at.is_some()
+    }
+
     pub fn created_at(&self) -> DelayedFormat<StrftimeItems<'_>> {
         self.created_at.format("%Y-%m-%d %H:%M")
     }
 }
 
+/// The account row, leaving out the password hash
+impl PersonalData for User {
+    const SECTION: &'static str = "account";
+
+    async fn export(ctx: &AppContext, user_pid: Uuid) -> Result<serde_json::Value> {
+        let user = Self::find_by_pid(&ctx.db, user_pid).await?;
+
+        Ok(json!({
+            "pid": user.pid,
+            "email": user.email,
+            "name": user.name,
+            "email_verified_at": user.email_verified_at,
+            "created_at": user.created_at,
+            "updated_at": user.updated_at,
+        }))
+    }
+}
+
 fn password_hash(plain_password: &str) -> Result<String> {
     let argon2 = Argon2::default();
     let salt = SaltString::generate(&mut OsRng);
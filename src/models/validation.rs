@@ -0,0 +1,271 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+};
+
+use axum::{
+    Json,
+    extract::{FromRequest, Request},
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{Result, models::ModelError};
+
+/// Longest email address SMTP can deliver to (RFC 5321)
+const EMAIL_MAX_LENGTH: usize = 254;
+
+/// Matches the `VARCHAR(255)` columns of the `users` table
+pub const NAME_MAX_LENGTH: usize = 255;
+
+/// Problems found in a request body, by field; returned to the client as
+/// `422 Unprocessable Entity`
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ValidationErrors(BTreeMap<String, Vec<String>>);
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.0.entry(field.into()).or_default().push(message.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// `Ok` if nothing was reported, otherwise a [`ModelError::Validation`]
+    pub fn into_result(self) -> Result<()> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(crate::Error::Model(ModelError::Validation(self)).into())
+        }
+    }
+}
+
+impl Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = self.0.keys().map(String::as_str).collect::<Vec<_>>();
+
+        write!(f, "invalid {}", fields.join(", "))
+    }
+}
+
+/// A JSON request body whose fields are reported like validation errors when they have the wrong
+/// type, so that every problem with a field comes back as `422` in the same shape.
+///
+/// Fields that may be missing should be `#[serde(default)]` and checked by the validation of the
+/// body instead. A body that isn't JSON is still rejected the way `Json` does.
+pub struct JsonBody<T>(pub T);
+
+impl<S: Send + Sync, T: DeserializeOwned> FromRequest<S> for JsonBody<T> {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<serde_json::Value>::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        serde_path_to_error::deserialize(value)
+            .map(Self)
+            .map_err(|err| {
+                let field = match err.path().to_string() {
+                    path if path == "." => "body".to_string(),
+                    path => path,
+                };
+
+                let mut errors = ValidationErrors::default();
+                errors.add(field, err.into_inner().to_string());
+
+                ModelError::Validation(errors).response()
+            })
+    }
+}
+
+/// Checks the shape of an address; whether it exists is for email verification to find out
+pub fn validate_email(field: &'static str, email: &str, errors: &mut ValidationErrors) {
+    let email = email.trim();
+
+    if email.is_empty() {
+        errors.add(field, "is required");
+        return;
+    }
+
+    if email.chars().count() > EMAIL_MAX_LENGTH {
+        errors.add(
+            field,
+            format!("must be at most {} characters", EMAIL_MAX_LENGTH),
+        );
+    }
+
+    let valid = email.split_once('@').is_some_and(|(local, domain)| {
+        !local.is_empty()
+            && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+            && domain.contains('.')
+            && domain.split('.').all(|label| {
+                !label.is_empty()
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+            })
+    });
+
+    if !valid {
+        errors.add(field, "is not a valid email address");
+    }
+}
+
+pub fn validate_name(field: &'static str, name: &str, errors: &mut ValidationErrors) {
+    let name = name.trim();
+
+    if name.is_empty() {
+        errors.add(field, "is required");
+    } else if name.chars().count() > NAME_MAX_LENGTH {
+        errors.add(
+            field,
+            format!("must be at most {} characters", NAME_MAX_LENGTH),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{Body, to_bytes},
+        http::{StatusCode, header::CONTENT_TYPE},
+    };
+    use serde::Deserialize;
+
+    use super::*;
+
+    fn email_errors(email: &str) -> ValidationErrors {
+        let mut errors = ValidationErrors::default();
+        validate_email("email", email, &mut errors);
+        errors
+    }
+
+    #[test]
+    fn accepts_valid_emails() {
+        for email in [
+            "user@example.com",
+            "first.last+tag@mail.example.co.uk",
+            "user@my-domain.example",
+            "user@bücher.de",
+            "  user@example.com  ",
+        ] {
+            assert!(email_errors(email).is_empty(), "{email} should be valid");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_emails() {
+        for email in [
+            "user.example.com",
+            "@example.com",
+            "user@",
+            "user@localhost",
+            "user@@example.com",
+            "user@exam@ple.com",
+            "user@.example.com",
+            "user@example..com",
+            "user@example.com.",
+            "user@-example.com",
+            "user@example-.com",
+            "user@exa_mple.com",
+            "us er@example.com",
+            "user@exam ple.com",
+            "user\t@example.com",
+        ] {
+            assert!(!email_errors(email).is_empty(), "{email} should be invalid");
+        }
+    }
+
+    #[test]
+    fn requires_an_email() {
+        assert_eq!(email_errors("   ").0["email"], ["is required"]);
+    }
+
+    #[test]
+    fn bounds_the_email_length() {
+        let local = "a".repeat(64);
+        let domain = format!("{}.com", "b".repeat(EMAIL_MAX_LENGTH - 64 - 5));
+        let email = format!("{local}@{domain}");
+        assert_eq!(email.len(), EMAIL_MAX_LENGTH);
+
+        assert!(email_errors(&email).is_empty());
+        assert_eq!(
+            email_errors(&format!("a{email}")).0["email"],
+            ["must be at most 254 characters"]
+        );
+    }
+
+    #[test]
+    fn checks_names() {
+        let check = |name: &str| {
+            let mut errors = ValidationErrors::default();
+            validate_name("name", name, &mut errors);
+            errors
+        };
+
+        assert!(check("Ada Lovelace").is_empty());
+        assert!(check(&"é".repeat(NAME_MAX_LENGTH)).is_empty());
+        assert_eq!(check(" ").0["name"], ["is required"]);
+        assert_eq!(
+            check(&"a".repeat(NAME_MAX_LENGTH + 1)).0["name"],
+            ["must be at most 255 characters"]
+        );
+    }
+
+    #[test]
+    fn collects_errors_by_field() {
+        let mut errors = ValidationErrors::default();
+        assert!(errors.clone().into_result().is_ok());
+
+        errors.add("password", "must contain a digit");
+        errors.add("email", "is required");
+        errors.add("password", "must contain a symbol");
+
+        assert_eq!(errors.to_string(), "invalid email, password");
+        assert_eq!(errors.0["password"].len(), 2);
+        assert!(errors.into_result().is_err());
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct EmailBody {
+        email: String,
+    }
+
+    async fn json_body(body: &'static str) -> Result<JsonBody<EmailBody>, Response> {
+        let req = Request::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap();
+
+        JsonBody::from_request(req, &()).await
+    }
+
+    #[tokio::test]
+    async fn json_body_reports_fields_of_the_wrong_type() {
+        assert_eq!(
+            json_body(r#"{"email":"a@b.c"}"#).await.unwrap().0.email,
+            "a@b.c"
+        );
+
+        let res = json_body(r#"{"email":123}"#).await.err().unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        assert_eq!(body["error"], "Validation failed");
+        assert_eq!(
+            body["fields"]["email"][0],
+            "invalid type: integer `123`, expected a string"
+        );
+    }
+
+    #[tokio::test]
+    async fn json_body_rejects_malformed_json() {
+        let res = json_body("{").await.err().unwrap();
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
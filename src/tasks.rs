@@ -0,0 +1,21 @@
+/// Background jobs started alongside the HTTP server
+use std::{sync::Arc, time::Duration};
+
+use crate::{context::AppContext, models::User};
+
+/// Permanently removes accounts whose deletion grace period is over, once every
+/// `purge_interval`
+pub async fn purge_deleted_users(ctx: Arc<AppContext>) {
+    let config = ctx.config.auth().account_deletion();
+    let mut interval = tokio::time::interval(Duration::from_secs(config.purge_interval()));
+
+    loop {
+        interval.tick().await;
+
+        match User::purge_deleted(&ctx.db, config.grace_period()).await {
+            Ok(0) => (),
+            Ok(purged) => tracing::info!(purged, "Purged deleted accounts"),
+            Err(err) => tracing::error!("Failed to purge deleted accounts: {}", err),
+        }
+    }
+}